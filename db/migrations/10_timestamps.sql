@@ -0,0 +1,31 @@
+-- Times were stored in chrono's Display format, e.g. `2024-01-31 12:00:00 UTC`,
+-- which doesn't compare correctly with RFC 3339 bounds. Store them all as
+-- `2024-01-31T12:00:00Z` instead.
+UPDATE user_repos SET date = strftime('%Y-%m-%dT%H:%M:%SZ', substr(date, 1, 19)) WHERE date LIKE '% UTC';
+UPDATE repository_snapshot SET taken_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(taken_at, 1, 19)) WHERE taken_at LIKE '% UTC';
+UPDATE user_influence SET computed_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(computed_at, 1, 19)) WHERE computed_at LIKE '% UTC';
+UPDATE community SET computed_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(computed_at, 1, 19)) WHERE computed_at LIKE '% UTC';
+UPDATE user SET last_seen_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(last_seen_at, 1, 19)) WHERE last_seen_at LIKE '% UTC';
+UPDATE repository SET last_seen_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(last_seen_at, 1, 19)) WHERE last_seen_at LIKE '% UTC';
+UPDATE user_name SET
+    first_seen_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(first_seen_at, 1, 19)),
+    last_seen_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(last_seen_at, 1, 19))
+WHERE first_seen_at LIKE '% UTC';
+UPDATE repository_name SET
+    first_seen_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(first_seen_at, 1, 19)),
+    last_seen_at = strftime('%Y-%m-%dT%H:%M:%SZ', substr(last_seen_at, 1, 19))
+WHERE first_seen_at LIKE '% UTC';
+
+-- When each row was first stored and last changed. Rows stored before this
+-- migration have no audit times.
+ALTER TABLE user ADD COLUMN created_at TEXT;
+ALTER TABLE user ADD COLUMN updated_at TEXT;
+ALTER TABLE repository ADD COLUMN created_at TEXT;
+ALTER TABLE repository ADD COLUMN updated_at TEXT;
+ALTER TABLE user_repos ADD COLUMN created_at TEXT;
+ALTER TABLE user_repos ADD COLUMN updated_at TEXT;
+ALTER TABLE user_users ADD COLUMN created_at TEXT;
+
+CREATE INDEX user_repos_repository ON user_repos (repository, type);
+CREATE INDEX user_repos_date ON user_repos (type, date);
+CREATE INDEX user_users_linked ON user_users (linked, type);
@@ -0,0 +1,23 @@
+-- Each refresh of the targets by `stargazers watch`
+CREATE TABLE sync_run (
+    id INTEGER PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    finished_at TEXT,
+    -- running, succeeded, failed, or interrupted if the process stopped
+    status TEXT NOT NULL,
+    duration_ms INTEGER,
+    requests INTEGER,
+    error TEXT
+);
+
+-- ETag and item count of each page of a target's stargazers, forks, issues
+-- and contributors when last fetched, so refreshes can skip unchanged pages
+CREATE TABLE sync_checkpoint (
+    repository INTEGER NOT NULL REFERENCES repository (id),
+    list TEXT NOT NULL,
+    page INTEGER NOT NULL,
+    etag TEXT NOT NULL,
+    items INTEGER NOT NULL,
+    updated_at TEXT NOT NULL,
+    UNIQUE (repository, list, page)
+);
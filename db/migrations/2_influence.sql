@@ -0,0 +1,11 @@
+CREATE TABLE target (
+    repository INTEGER PRIMARY KEY,
+    FOREIGN KEY (repository) REFERENCES repository (id)
+);
+
+CREATE TABLE user_influence (
+    user INTEGER PRIMARY KEY,
+    score REAL NOT NULL,
+    computed_at TEXT NOT NULL,
+    FOREIGN KEY (user) REFERENCES user (id)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE community (
+    id INTEGER PRIMARY KEY,
+    members INTEGER NOT NULL,
+    computed_at TEXT NOT NULL
+);
+
+CREATE TABLE community_repos (
+    community INTEGER NOT NULL,
+    repository INTEGER NOT NULL,
+    stargazers INTEGER NOT NULL,
+    FOREIGN KEY (community) REFERENCES community (id),
+    FOREIGN KEY (repository) REFERENCES repository (id),
+    UNIQUE(repository)
+);
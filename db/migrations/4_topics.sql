@@ -0,0 +1,8 @@
+ALTER TABLE repository ADD COLUMN language VARCHAR;
+
+CREATE TABLE repository_topic (
+    repository INTEGER NOT NULL,
+    topic VARCHAR NOT NULL,
+    FOREIGN KEY (repository) REFERENCES repository (id),
+    UNIQUE(repository, topic)
+);
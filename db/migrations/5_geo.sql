@@ -0,0 +1,3 @@
+ALTER TABLE user ADD COLUMN location VARCHAR;
+ALTER TABLE user ADD COLUMN country VARCHAR;
+ALTER TABLE user ADD COLUMN city VARCHAR;
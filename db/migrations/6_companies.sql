@@ -0,0 +1,11 @@
+ALTER TABLE user ADD COLUMN company VARCHAR;
+
+CREATE TABLE company_alias (
+    alias VARCHAR PRIMARY KEY,
+    company VARCHAR NOT NULL
+);
+
+CREATE TABLE organization (
+    login VARCHAR PRIMARY KEY,
+    name VARCHAR
+);
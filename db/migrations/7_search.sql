@@ -0,0 +1,67 @@
+ALTER TABLE user ADD COLUMN bio VARCHAR;
+ALTER TABLE repository ADD COLUMN description VARCHAR;
+
+CREATE VIRTUAL TABLE user_search USING fts5(
+    username,
+    name,
+    company,
+    bio,
+    content = 'user',
+    content_rowid = 'id'
+);
+
+CREATE TRIGGER user_search_insert AFTER INSERT ON user BEGIN
+    INSERT INTO user_search (rowid, username, name, company, bio)
+    VALUES (new.id, new.username, new.name, new.company, new.bio);
+END;
+
+CREATE TRIGGER user_search_delete AFTER DELETE ON user BEGIN
+    INSERT INTO user_search (user_search, rowid, username, name, company, bio)
+    VALUES ('delete', old.id, old.username, old.name, old.company, old.bio);
+END;
+
+CREATE TRIGGER user_search_update AFTER UPDATE OF username, name, company, bio ON user BEGIN
+    INSERT INTO user_search (user_search, rowid, username, name, company, bio)
+    VALUES ('delete', old.id, old.username, old.name, old.company, old.bio);
+    INSERT INTO user_search (rowid, username, name, company, bio)
+    VALUES (new.id, new.username, new.name, new.company, new.bio);
+END;
+
+INSERT INTO user_search (user_search) VALUES ('rebuild');
+
+-- Topics live in their own table, so the repo index keeps its own copy of
+-- the text rather than reading it from `repository`
+CREATE VIRTUAL TABLE repository_search USING fts5(full_name, description, topics);
+
+CREATE TRIGGER repository_search_insert AFTER INSERT ON repository BEGIN
+    INSERT INTO repository_search (rowid, full_name, description, topics)
+    VALUES (new.id, new.full_name, new.description, '');
+END;
+
+CREATE TRIGGER repository_search_delete AFTER DELETE ON repository BEGIN
+    DELETE FROM repository_search WHERE rowid = old.id;
+END;
+
+CREATE TRIGGER repository_search_update AFTER UPDATE OF full_name, description ON repository BEGIN
+    UPDATE repository_search
+    SET full_name = new.full_name, description = new.description
+    WHERE rowid = new.id;
+END;
+
+CREATE TRIGGER repository_search_topic_insert AFTER INSERT ON repository_topic BEGIN
+    UPDATE repository_search
+    SET topics = (SELECT group_concat(topic, ' ') FROM repository_topic WHERE repository = new.repository)
+    WHERE rowid = new.repository;
+END;
+
+CREATE TRIGGER repository_search_topic_delete AFTER DELETE ON repository_topic BEGIN
+    UPDATE repository_search
+    SET topics = (SELECT group_concat(topic, ' ') FROM repository_topic WHERE repository = old.repository)
+    WHERE rowid = old.repository;
+END;
+
+INSERT INTO repository_search (rowid, full_name, description, topics)
+SELECT id, full_name, description, (
+    SELECT group_concat(topic, ' ') FROM repository_topic WHERE repository = r.id
+)
+FROM repository r;
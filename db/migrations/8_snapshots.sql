@@ -0,0 +1,6 @@
+CREATE TABLE repository_snapshot (
+    repository INTEGER NOT NULL,
+    stargazers INTEGER NOT NULL,
+    taken_at TEXT NOT NULL,
+    FOREIGN KEY (repository) REFERENCES repository (id)
+);
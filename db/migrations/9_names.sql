@@ -0,0 +1,29 @@
+ALTER TABLE user ADD COLUMN last_seen_at TEXT;
+ALTER TABLE repository ADD COLUMN last_seen_at TEXT;
+
+-- Every login a user has been seen with. A user's `username` is cleared
+-- when another account takes it over, until they are seen again.
+CREATE TABLE user_name (
+    user INTEGER NOT NULL,
+    username VARCHAR NOT NULL,
+    first_seen_at TEXT,
+    last_seen_at TEXT,
+    FOREIGN KEY (user) REFERENCES user (id),
+    UNIQUE(user, username)
+);
+
+INSERT INTO user_name (user, username)
+SELECT id, username FROM user WHERE username IS NOT NULL;
+
+-- Every name a repo has had, across renames and transfers
+CREATE TABLE repository_name (
+    repository INTEGER NOT NULL,
+    full_name VARCHAR NOT NULL,
+    first_seen_at TEXT,
+    last_seen_at TEXT,
+    FOREIGN KEY (repository) REFERENCES repository (id),
+    UNIQUE(repository, full_name)
+);
+
+INSERT INTO repository_name (repository, full_name)
+SELECT id, full_name FROM repository WHERE full_name IS NOT NULL;
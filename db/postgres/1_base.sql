@@ -0,0 +1,96 @@
+-- PostgreSQL counterpart of the SQLite migrations in db/migrations, with the
+-- same tables and columns. `user` is a reserved word here, so it is quoted.
+
+CREATE TABLE "user" (
+    id BIGINT PRIMARY KEY,
+    username VARCHAR UNIQUE,
+    name VARCHAR,
+    email VARCHAR,
+    location VARCHAR,
+    country VARCHAR,
+    city VARCHAR,
+    company VARCHAR,
+    bio VARCHAR,
+    -- Weighted like the SQLite `user_search` index: login, name, company, bio
+    search TSVECTOR GENERATED ALWAYS AS (
+        setweight(to_tsvector('simple', regexp_replace(coalesce(username, ''), '[^[:alnum:]]+', ' ', 'g')), 'A') ||
+        setweight(to_tsvector('simple', regexp_replace(coalesce(name, ''), '[^[:alnum:]]+', ' ', 'g')), 'B') ||
+        setweight(to_tsvector('simple', regexp_replace(coalesce(company, ''), '[^[:alnum:]]+', ' ', 'g')), 'C') ||
+        setweight(to_tsvector('simple', regexp_replace(coalesce(bio, ''), '[^[:alnum:]]+', ' ', 'g')), 'D')
+    ) STORED
+);
+
+CREATE INDEX user_search ON "user" USING GIN (search);
+
+CREATE TABLE repository (
+    id BIGINT PRIMARY KEY,
+    full_name VARCHAR UNIQUE,
+    stargazers BIGINT NOT NULL,
+    language VARCHAR,
+    description VARCHAR,
+    -- Topics live in their own table and are added when searching
+    search TSVECTOR GENERATED ALWAYS AS (
+        setweight(to_tsvector('simple', regexp_replace(coalesce(full_name, ''), '[^[:alnum:]]+', ' ', 'g')), 'A') ||
+        setweight(to_tsvector('simple', regexp_replace(coalesce(description, ''), '[^[:alnum:]]+', ' ', 'g')), 'C')
+    ) STORED
+);
+
+CREATE TABLE user_repos (
+    "user" BIGINT NOT NULL REFERENCES "user" (id),
+    repository BIGINT NOT NULL REFERENCES repository (id),
+    type VARCHAR NOT NULL,
+    date TEXT,
+    UNIQUE ("user", repository, type)
+);
+
+CREATE TABLE user_users (
+    subject BIGINT NOT NULL REFERENCES "user" (id),
+    linked BIGINT NOT NULL REFERENCES "user" (id),
+    type VARCHAR NOT NULL,
+    UNIQUE (subject, linked, type)
+);
+
+CREATE TABLE target (
+    repository BIGINT PRIMARY KEY REFERENCES repository (id)
+);
+
+CREATE TABLE repository_snapshot (
+    repository BIGINT NOT NULL REFERENCES repository (id),
+    stargazers BIGINT NOT NULL,
+    taken_at TEXT NOT NULL
+);
+
+CREATE TABLE user_influence (
+    "user" BIGINT PRIMARY KEY REFERENCES "user" (id),
+    score DOUBLE PRECISION NOT NULL,
+    computed_at TEXT NOT NULL
+);
+
+CREATE TABLE community (
+    id BIGINT PRIMARY KEY,
+    members BIGINT NOT NULL,
+    computed_at TEXT NOT NULL
+);
+
+CREATE TABLE community_repos (
+    community BIGINT NOT NULL REFERENCES community (id),
+    repository BIGINT NOT NULL REFERENCES repository (id),
+    stargazers BIGINT NOT NULL,
+    UNIQUE (repository)
+);
+
+CREATE TABLE repository_topic (
+    repository BIGINT NOT NULL REFERENCES repository (id),
+    topic VARCHAR NOT NULL,
+    UNIQUE (repository, topic)
+);
+
+CREATE TABLE company_alias (
+    alias VARCHAR PRIMARY KEY,
+    company VARCHAR NOT NULL
+);
+
+CREATE TABLE organization (
+    login VARCHAR PRIMARY KEY,
+    name VARCHAR
+);
@@ -0,0 +1,31 @@
+-- Times were stored in chrono's Display format, e.g. `2024-01-31 12:00:00 UTC`,
+-- which doesn't compare correctly with RFC 3339 bounds. Store them all as
+-- `2024-01-31T12:00:00Z` instead.
+UPDATE user_repos SET date = to_char(substr(date, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') WHERE date LIKE '% UTC';
+UPDATE repository_snapshot SET taken_at = to_char(substr(taken_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') WHERE taken_at LIKE '% UTC';
+UPDATE user_influence SET computed_at = to_char(substr(computed_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') WHERE computed_at LIKE '% UTC';
+UPDATE community SET computed_at = to_char(substr(computed_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') WHERE computed_at LIKE '% UTC';
+UPDATE "user" SET last_seen_at = to_char(substr(last_seen_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') WHERE last_seen_at LIKE '% UTC';
+UPDATE repository SET last_seen_at = to_char(substr(last_seen_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') WHERE last_seen_at LIKE '% UTC';
+UPDATE user_name SET
+    first_seen_at = to_char(substr(first_seen_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"'),
+    last_seen_at = to_char(substr(last_seen_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"')
+WHERE first_seen_at LIKE '% UTC';
+UPDATE repository_name SET
+    first_seen_at = to_char(substr(first_seen_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"'),
+    last_seen_at = to_char(substr(last_seen_at, 1, 19)::timestamp, 'YYYY-MM-DD"T"HH24:MI:SS"Z"')
+WHERE first_seen_at LIKE '% UTC';
+
+-- When each row was first stored and last changed. Rows stored before this
+-- migration have no audit times.
+ALTER TABLE "user" ADD COLUMN created_at TEXT;
+ALTER TABLE "user" ADD COLUMN updated_at TEXT;
+ALTER TABLE repository ADD COLUMN created_at TEXT;
+ALTER TABLE repository ADD COLUMN updated_at TEXT;
+ALTER TABLE user_repos ADD COLUMN created_at TEXT;
+ALTER TABLE user_repos ADD COLUMN updated_at TEXT;
+ALTER TABLE user_users ADD COLUMN created_at TEXT;
+
+CREATE INDEX user_repos_repository ON user_repos (repository, type);
+CREATE INDEX user_repos_date ON user_repos (type, date);
+CREATE INDEX user_users_linked ON user_users (linked, type);
@@ -0,0 +1,14 @@
+-- Databases and exported files merged in with `stargazers merge` and
+-- `stargazers import`
+CREATE TABLE import (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    source TEXT NOT NULL,
+    imported_at TEXT NOT NULL
+);
+
+-- The import that each row's values came from, or NULL if they were
+-- downloaded into this database
+ALTER TABLE "user" ADD COLUMN import BIGINT REFERENCES import (id);
+ALTER TABLE repository ADD COLUMN import BIGINT REFERENCES import (id);
+ALTER TABLE user_repos ADD COLUMN import BIGINT REFERENCES import (id);
+ALTER TABLE user_users ADD COLUMN import BIGINT REFERENCES import (id);
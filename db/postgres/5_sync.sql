@@ -0,0 +1,23 @@
+-- Each refresh of the targets by `stargazers watch`
+CREATE TABLE sync_run (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    finished_at TEXT,
+    -- running, succeeded, failed, or interrupted if the process stopped
+    status TEXT NOT NULL,
+    duration_ms BIGINT,
+    requests BIGINT,
+    error TEXT
+);
+
+-- ETag and item count of each page of a target's stargazers, forks, issues
+-- and contributors when last fetched, so refreshes can skip unchanged pages
+CREATE TABLE sync_checkpoint (
+    repository BIGINT NOT NULL REFERENCES repository (id),
+    list TEXT NOT NULL,
+    page BIGINT NOT NULL,
+    etag TEXT NOT NULL,
+    items BIGINT NOT NULL,
+    updated_at TEXT NOT NULL,
+    UNIQUE (repository, list, page)
+);
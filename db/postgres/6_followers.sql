@@ -0,0 +1,3 @@
+-- Follower count on GitHub when the profile was fetched, which weights
+-- `compute influence --weighted`
+ALTER TABLE "user" ADD COLUMN followers BIGINT;
@@ -0,0 +1,6 @@
+-- When the user's profile was last fetched. Users stored from lists, such as
+-- followees or contributors, have none until they are crawled as
+-- stargazers. Only fetched profiles set a follower count, so that marks the
+-- ones fetched before this migration.
+ALTER TABLE "user" ADD COLUMN profiled_at TEXT;
+UPDATE "user" SET profiled_at = coalesce(updated_at, last_seen_at) WHERE followers IS NOT NULL;
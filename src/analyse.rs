@@ -1,42 +1,73 @@
-use std::path::{Path, PathBuf};
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::Result;
 use rocket::{
     fairing::{Fairing, Info, Kind},
-    fs::{relative, NamedFile},
+    fs::NamedFile,
     get,
-    http::Header,
-    options, routes,
-    serde::json::Json,
-    Request, Response,
+    http::{ContentType, Header},
+    options, routes, Request, Responder, Response, State,
 };
-use rocket_db_pools::{sqlx, Connection, Database};
+use rust_embed::RustEmbed;
+
+use crate::{api, config::ServerConfig, db, jobs::Jobs};
+
+/// The database, shared by every API request and by the jobs
+pub(crate) type Stargazers = Arc<dyn db::Storage>;
 
-use crate::db::{get_top_following, get_top_repos, get_top_subscribed};
+/// The built frontend, embedded so the binary works from any directory. Run
+/// `npm run build` in `web/` before building to include it.
+#[derive(RustEmbed)]
+#[folder = "web/dist"]
+#[allow_missing = true]
+struct Assets;
 
-#[derive(Database)]
-#[database("stargazers")]
-pub(crate) struct Stargazers(sqlx::SqlitePool);
+/// Serve the frontend from this directory instead of the embedded copy
+struct WebDir(Option<PathBuf>);
+
+#[derive(Responder)]
+enum WebFile {
+    Disk(NamedFile),
+    Embedded((ContentType, Cow<'static, [u8]>)),
+}
 
+/// Lets the frontend's dev server, on another origin, call the API
 #[allow(dead_code)]
-struct Cors;
-
-pub async fn analyse(open: bool) -> Result<()> {
-    let builder = rocket::build()
-        .mount(
-            "/",
-            routes![
-                static_web,
-                top_repos,
-                top_following,
-                top_subscribed,
-                all_options
-            ],
-        )
-        .attach(Stargazers::init());
+struct Cors(String);
+
+pub async fn analyse(
+    db: Stargazers,
+    server: &ServerConfig,
+    jobs: Jobs,
+    open: bool,
+    web_dir: Option<&Path>,
+) -> Result<()> {
+    let figment = rocket::Config::figment()
+        .merge(("address", server.address))
+        .merge(("port", server.port));
+    let builder = rocket::custom(figment)
+        .manage(db)
+        .manage(jobs)
+        .manage(api::ApiToken(server.api_token.clone()))
+        .manage(WebDir(web_dir.map(Path::to_path_buf)))
+        .mount("/", routes![static_web, all_options])
+        .mount(api::BASE, api::routes())
+        .mount("/api", routes![api::openapi])
+        .register(api::BASE, api::catchers());
+
+    #[cfg(feature = "swagger-ui")]
+    let builder = builder.mount(
+        "/",
+        utoipa_swagger_ui::SwaggerUi::new("/api/docs/<_..>")
+            .config(utoipa_swagger_ui::Config::from("/api/openapi.json")),
+    );
 
     #[cfg(debug_assertions)]
-    let builder = builder.attach(Cors);
+    let builder = builder.attach(Cors(server.cors_origin.clone()));
 
     let server = builder.ignite().await?;
 
@@ -60,46 +91,47 @@ pub async fn analyse(open: bool) -> Result<()> {
 }
 
 #[get("/<path..>")]
-async fn static_web(path: PathBuf) -> Option<NamedFile> {
-    let mut path = Path::new(relative!("web/dist")).join(path);
-    if path.is_dir() {
-        path.push("index.html");
-    }
-
-    NamedFile::open(path).await.ok()
-}
-
-#[get("/repos/top/<num>", format = "json")]
-async fn top_repos(
-    mut db: Connection<Stargazers>,
-    num: u32,
-) -> std::result::Result<Json<Vec<(String, u32)>>, String> {
-    match get_top_repos(&mut db, num).await {
-        Ok(repos) => Ok(Json(repos)),
-        Err(e) => Err(e.to_string()),
+async fn static_web(path: PathBuf, web_dir: &State<WebDir>) -> Option<WebFile> {
+    // Let the frontend router handle deep links such as /repos/<owner>/<name>
+    let fallback = !path.starts_with("api");
+
+    match &web_dir.0 {
+        Some(dir) => {
+            let mut file = dir.join(&path);
+            if file.is_dir() {
+                file.push("index.html");
+            }
+
+            match NamedFile::open(file).await {
+                Ok(file) => Some(WebFile::Disk(file)),
+                Err(_) if fallback => NamedFile::open(dir.join("index.html"))
+                    .await
+                    .ok()
+                    .map(WebFile::Disk),
+                Err(_) => None,
+            }
+        }
+        None => {
+            let mut name = path.to_string_lossy().into_owned();
+            if name.is_empty() || name.ends_with('/') {
+                name.push_str("index.html");
+            }
+
+            embedded(&name)
+                .or_else(|| embedded(&format!("{name}/index.html")))
+                .or_else(|| fallback.then(|| embedded("index.html")).flatten())
+        }
     }
 }
 
-#[get("/following/top/<num>", format = "json")]
-async fn top_following(
-    mut db: Connection<Stargazers>,
-    num: u32,
-) -> std::result::Result<Json<Vec<(String, u32)>>, String> {
-    match get_top_following(&mut db, num).await {
-        Ok(followed) => Ok(Json(followed)),
-        Err(e) => Err(e.to_string()),
-    }
-}
+fn embedded(name: &str) -> Option<WebFile> {
+    let file = Assets::get(name)?;
+    let content_type = Path::new(name)
+        .extension()
+        .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy()))
+        .unwrap_or(ContentType::Binary);
 
-#[get("/subscribed/top/<num>", format = "json")]
-async fn top_subscribed(
-    mut db: Connection<Stargazers>,
-    num: u32,
-) -> std::result::Result<Json<Vec<(String, u32)>>, String> {
-    match get_top_subscribed(&mut db, num).await {
-        Ok(repos) => Ok(Json(repos)),
-        Err(e) => Err(e.to_string()),
-    }
+    Some(WebFile::Embedded((content_type, file.data)))
 }
 
 /// Catches all OPTION requests in order to get the CORS related Fairing triggered.
@@ -116,13 +148,20 @@ impl Fairing for Cors {
         }
     }
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_header(Header::new("Vary", "Origin"));
+        if request.headers().get_one("Origin") != Some(&self.0) {
+            return;
+        }
+
+        response.set_header(Header::new("Access-Control-Allow-Origin", self.0.clone()));
         response.set_header(Header::new(
             "Access-Control-Allow-Methods",
             "POST, PATCH, PUT, DELETE, HEAD, OPTIONS, GET",
         ));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        response.set_header(Header::new(
+            "Access-Control-Allow-Headers",
+            "Authorization, Content-Type",
+        ));
     }
 }
@@ -0,0 +1,871 @@
+use chrono::{DateTime, Utc};
+use rocket::{
+    catch, catchers, delete,
+    form::{self, FromForm},
+    get,
+    http::Status,
+    post,
+    request::{FromRequest, Outcome},
+    response::{
+        self,
+        status::Created,
+        stream::{Event, EventStream},
+        Responder,
+    },
+    routes,
+    serde::json::Json,
+    Catcher, Request, Response, Route, Shutdown, State,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    analyse::Stargazers,
+    company::top_companies,
+    db::{
+        self, Cohort, Community, CompanyCount, CountryCount, Filter, LanguageScore, NotFound, Page,
+        Paginated, RepoCount, RepoDetail, RepoHit, RepoScore, TopicScore, UserCount, UserDetail,
+        UserHit, UserScore,
+    },
+    geo,
+    jobs::{JobState, JobStatus, Jobs, StartError},
+    recommend::{recommend_repos, similar_repos},
+    search,
+};
+
+pub const BASE: &str = "/api/v1";
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 1000;
+const EDGE_TYPES: [&str; 5] = [
+    "stargazer",
+    "subscriber",
+    "forker",
+    "issue_author",
+    "contributor",
+];
+
+/// Query parameters shared by the list endpoints that count stargazers
+#[derive(FromForm, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+struct Params<'r> {
+    #[field(default = DEFAULT_LIMIT, validate = range(1..=MAX_LIMIT as isize))]
+    #[param(required = false, default = 50, minimum = 1, maximum = 1000)]
+    limit: u32,
+    #[field(default = 0)]
+    #[param(required = false, default = 0)]
+    offset: u32,
+    /// `next_cursor` from a previous page, which is the offset of the page
+    /// after it. Takes precedence over `offset`.
+    cursor: Option<&'r str>,
+    /// Only count stargazers who starred on or after this date
+    since: Option<&'r str>,
+    /// Only count stargazers who starred before this date
+    until: Option<&'r str>,
+    /// Target repo whose stargazers are counted (defaults to all targets)
+    repo: Option<&'r str>,
+}
+
+/// The `type` query parameter of `/repos/top`. Rocket can't bind a parameter
+/// named after a keyword, so it is read by this guard.
+struct EdgeType(&'static str);
+
+/// Query parameters for endpoints that page through results but take no
+/// filters
+#[derive(FromForm, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+struct PageParams<'r> {
+    #[field(default = DEFAULT_LIMIT, validate = range(1..=MAX_LIMIT as isize))]
+    #[param(required = false, default = 50, minimum = 1, maximum = 1000)]
+    limit: u32,
+    #[field(default = 0)]
+    #[param(required = false, default = 0)]
+    offset: u32,
+    /// `next_cursor` from a previous page, which is the offset of the page
+    /// after it. Takes precedence over `offset`.
+    cursor: Option<&'r str>,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: Status,
+    message: String,
+}
+
+/// Token clients must send as `Authorization: Bearer <token>` to start or
+/// cancel jobs, from `server.api_token`
+pub struct ApiToken(pub Option<String>);
+
+/// Request guard for starting and cancelling jobs, which spend the server's
+/// GitHub quota. Without a configured token, only a server listening on a
+/// loopback address accepts them.
+struct JobAccess;
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct SearchResults {
+    pub users: Vec<UserHit>,
+    pub repos: Vec<RepoHit>,
+}
+
+/// A download to start
+#[derive(Deserialize, ToSchema, Debug)]
+struct NewJob {
+    /// Repo to download (owner/name)
+    repo: String,
+    /// Only fetch what changed since the last download
+    #[serde(default)]
+    incremental: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ErrorDetail {
+    status: u16,
+    message: String,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Stargazers", description = "Analytics for GitHub stargazers"),
+    paths(
+        top_repos,
+        top_following,
+        top_topics,
+        top_languages,
+        influential_users,
+        communities,
+        stargazer_geo,
+        stargazer_companies,
+        stargazer_cohorts,
+        similar,
+        recommendations,
+        user_detail,
+        repo_detail,
+        search_all,
+        list_jobs,
+        start_job,
+        job,
+        cancel_job,
+        job_events,
+    )
+)]
+pub struct ApiDoc;
+
+type ApiResult<T> = std::result::Result<Json<Paginated<T>>, ApiError>;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        top_repos,
+        top_following,
+        top_topics,
+        top_languages,
+        influential_users,
+        communities,
+        stargazer_geo,
+        stargazer_companies,
+        stargazer_cohorts,
+        similar,
+        recommendations,
+        user_detail,
+        repo_detail,
+        search_all,
+        list_jobs,
+        start_job,
+        job,
+        cancel_job,
+        job_events,
+    ]
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
+}
+
+/// Serve the OpenAPI document describing [`routes`]
+#[get("/openapi.json")]
+pub fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Repos ranked by how many users have an edge of `type` to them
+#[utoipa::path(
+    get,
+    path = "/api/v1/repos/top",
+    params(
+        Params,
+        ("type" = Option<String>, Query, description = "Edge type to rank by: stargazer (the default), subscriber, forker, issue_author or contributor"),
+    ),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<RepoCount>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/repos/top?<params..>", format = "json")]
+async fn top_repos(
+    db: &State<Stargazers>,
+    kind: Result<EdgeType, ApiError>,
+    params: form::Result<'_, Params<'_>>,
+) -> ApiResult<RepoCount> {
+    let kind = kind?.0;
+    let params = params?;
+    let page = params.page()?;
+    let repos = db
+        .get_top_repos(kind, &params.filter()?, page.lookahead())
+        .await?;
+    Ok(Json(Paginated::new(repos, page)))
+}
+
+/// Users ranked by how many of the crawled users follow them
+#[utoipa::path(
+    get,
+    path = "/api/v1/following/top",
+    params(Params),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<UserCount>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/following/top?<params..>", format = "json")]
+async fn top_following(
+    db: &State<Stargazers>,
+    params: form::Result<'_, Params<'_>>,
+) -> ApiResult<UserCount> {
+    let params = params?;
+    let page = params.page()?;
+    let users = db
+        .get_top_following(&params.filter()?, page.lookahead())
+        .await?;
+    Ok(Json(Paginated::new(users, page)))
+}
+
+/// Topics of starred repos, weighted so each stargazer counts once
+#[utoipa::path(
+    get,
+    path = "/api/v1/topics/top",
+    params(Params),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<TopicScore>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/topics/top?<params..>", format = "json")]
+async fn top_topics(
+    db: &State<Stargazers>,
+    params: form::Result<'_, Params<'_>>,
+) -> ApiResult<TopicScore> {
+    let params = params?;
+    let page = params.page()?;
+    let topics = db
+        .get_top_topics(&params.filter()?, page.lookahead())
+        .await?;
+    Ok(Json(Paginated::new(topics, page)))
+}
+
+/// Primary languages of starred repos, weighted so each stargazer counts once
+#[utoipa::path(
+    get,
+    path = "/api/v1/languages/top",
+    params(Params),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<LanguageScore>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/languages/top?<params..>", format = "json")]
+async fn top_languages(
+    db: &State<Stargazers>,
+    params: form::Result<'_, Params<'_>>,
+) -> ApiResult<LanguageScore> {
+    let params = params?;
+    let page = params.page()?;
+    let languages = db
+        .get_top_languages(&params.filter()?, page.lookahead())
+        .await?;
+    Ok(Json(Paginated::new(languages, page)))
+}
+
+/// Stargazers ranked by their PageRank over the follow graph
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/influential",
+    params(Params),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<UserScore>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/users/influential?<params..>", format = "json")]
+async fn influential_users(
+    db: &State<Stargazers>,
+    params: form::Result<'_, Params<'_>>,
+) -> ApiResult<UserScore> {
+    let params = params?;
+    let page = params.page()?;
+    let users = db
+        .get_influential_stargazers(&params.filter()?, page.lookahead())
+        .await?;
+    Ok(Json(Paginated::new(users, page)))
+}
+
+/// Communities of co-starred repos, with their most starred repos
+#[utoipa::path(
+    get,
+    path = "/api/v1/communities",
+    params(
+        PageParams,
+        ("repos" = Option<u32>, Query, description = "Top repos listed per community, 20 by default"),
+    ),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<Community>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/communities?<repos>&<params..>", format = "json")]
+async fn communities(
+    db: &State<Stargazers>,
+    repos: Option<u32>,
+    params: form::Result<'_, PageParams<'_>>,
+) -> ApiResult<Community> {
+    let page = params?.page()?;
+    let communities = db
+        .get_communities(page.lookahead(), repos.unwrap_or(20))
+        .await?;
+    Ok(Json(Paginated::new(communities, page)))
+}
+
+/// Stargazer counts by country
+#[utoipa::path(
+    get,
+    path = "/api/v1/stargazers/geo",
+    params(Params),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<CountryCount>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/stargazers/geo?<params..>", format = "json")]
+async fn stargazer_geo(
+    db: &State<Stargazers>,
+    params: form::Result<'_, Params<'_>>,
+) -> ApiResult<CountryCount> {
+    let params = params?;
+    let page = params.page()?;
+    let mut countries = db
+        .get_stargazer_countries(&params.filter()?, page.lookahead())
+        .await?;
+    for c in &mut countries {
+        c.code = geo::country_code(&c.country).map(str::to_owned);
+    }
+    Ok(Json(Paginated::new(countries, page)))
+}
+
+/// Stargazer counts by normalised employer
+#[utoipa::path(
+    get,
+    path = "/api/v1/stargazers/companies",
+    params(Params),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<CompanyCount>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/stargazers/companies?<params..>", format = "json")]
+async fn stargazer_companies(
+    db: &State<Stargazers>,
+    params: form::Result<'_, Params<'_>>,
+) -> ApiResult<CompanyCount> {
+    let params = params?;
+    let page = params.page()?;
+    let companies = top_companies(db.inner().as_ref(), &params.filter()?, page.lookahead()).await?;
+    Ok(Json(Paginated::new(companies, page)))
+}
+
+/// Stargazers grouped by the month they starred, with how many went on to engage
+#[utoipa::path(
+    get,
+    path = "/api/v1/stargazers/cohorts",
+    params(Params),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<Cohort>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/stargazers/cohorts?<params..>", format = "json")]
+async fn stargazer_cohorts(
+    db: &State<Stargazers>,
+    params: form::Result<'_, Params<'_>>,
+) -> ApiResult<Cohort> {
+    let params = params?;
+    let page = params.page()?;
+    let cohorts = db.get_cohorts(&params.filter()?, page.lookahead()).await?;
+    Ok(Json(Paginated::new(cohorts, page)))
+}
+
+/// Repos most often starred alongside `owner/name`
+#[utoipa::path(
+    get,
+    path = "/api/v1/repos/{owner}/{name}/similar",
+    params(PageParams, ("owner" = String, Path), ("name" = String, Path)),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<RepoScore>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 404, description = "Unknown repository or user", body = ErrorBody),
+    )
+)]
+#[get("/repos/<owner>/<name>/similar?<params..>", format = "json")]
+async fn similar(
+    db: &State<Stargazers>,
+    owner: &str,
+    name: &str,
+    params: form::Result<'_, PageParams<'_>>,
+) -> ApiResult<RepoScore> {
+    let page = params?.page()?;
+    let full_name = format!("{owner}/{name}");
+    let repos = similar_repos(db.inner().as_ref(), &full_name, page.lookahead()).await?;
+    Ok(Json(Paginated::new(repos, page)))
+}
+
+/// Repos `login` hasn't starred but might like
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{login}/recommendations",
+    params(PageParams, ("login" = String, Path)),
+    responses(
+        (status = 200, description = "One page of results", body = Paginated<RepoScore>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 404, description = "Unknown repository or user", body = ErrorBody),
+    )
+)]
+#[get("/users/<login>/recommendations?<params..>", format = "json")]
+async fn recommendations(
+    db: &State<Stargazers>,
+    login: &str,
+    params: form::Result<'_, PageParams<'_>>,
+) -> ApiResult<RepoScore> {
+    let page = params?.page()?;
+    let repos = recommend_repos(db.inner().as_ref(), login, page.lookahead()).await?;
+    Ok(Json(Paginated::new(repos, page)))
+}
+
+/// A stored user with their stars, subscriptions and follows
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{login}",
+    params(("login" = String, Path)),
+    responses(
+        (status = 200, description = "The user", body = UserDetail),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+#[get("/users/<login>", format = "json")]
+async fn user_detail(db: &State<Stargazers>, login: &str) -> Result<Json<UserDetail>, ApiError> {
+    match db.get_user_detail(login).await? {
+        Some(user) => Ok(Json(user)),
+        None => Err(NotFound(format!("User {login}")).into()),
+    }
+}
+
+/// A stored repo and which of our stargazers starred it. `limit`, `offset`
+/// and `cursor` page through the stargazers in `audience`.
+#[utoipa::path(
+    get,
+    path = "/api/v1/repos/{owner}/{name}",
+    params(Params, ("owner" = String, Path), ("name" = String, Path)),
+    responses(
+        (status = 200, description = "The repo", body = RepoDetail),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 404, description = "Unknown repository", body = ErrorBody),
+    )
+)]
+#[get("/repos/<owner>/<name>?<params..>", format = "json")]
+async fn repo_detail(
+    db: &State<Stargazers>,
+    owner: &str,
+    name: &str,
+    params: form::Result<'_, Params<'_>>,
+) -> Result<Json<RepoDetail>, ApiError> {
+    let params = params?;
+    let full_name = format!("{owner}/{name}");
+    match db
+        .get_repo_detail(&full_name, &params.filter()?, params.page()?)
+        .await?
+    {
+        Some(repo) => Ok(Json(repo)),
+        None => Err(NotFound(format!("Repository {full_name}")).into()),
+    }
+}
+
+/// Users and repos matching every word in `q`, as prefixes. `limit` and
+/// `offset` apply to each list.
+#[utoipa::path(
+    get,
+    path = "/api/v1/search",
+    params(("q" = String, Query, description = "Words to search for"), PageParams),
+    responses(
+        (status = 200, description = "Matching users and repos", body = SearchResults),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+    )
+)]
+#[get("/search?<q>&<params..>", format = "json")]
+async fn search_all(
+    db: &State<Stargazers>,
+    q: Option<&str>,
+    params: form::Result<'_, PageParams<'_>>,
+) -> Result<Json<SearchResults>, ApiError> {
+    let page = params?.page()?;
+    let words = q.map(search::words).unwrap_or_default();
+    if words.is_empty() {
+        return Err(ApiError::bad_request("q is required"));
+    }
+
+    Ok(Json(SearchResults {
+        users: db.search_users(&words, page).await?,
+        repos: db.search_repos(&words, page).await?,
+    }))
+}
+
+/// Downloads started through the API since the server started, newest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/jobs",
+    responses((status = 200, description = "Every job", body = Vec<JobState>))
+)]
+#[get("/jobs", format = "json")]
+fn list_jobs(jobs: &State<Jobs>) -> Json<Vec<JobState>> {
+    Json(jobs.list())
+}
+
+/// Start downloading a repo and its stargazers in the background
+#[utoipa::path(
+    post,
+    path = "/api/v1/jobs",
+    request_body = NewJob,
+    responses(
+        (status = 201, description = "The job, now running", body = JobState),
+        (status = 400, description = "Invalid repo", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API token", body = ErrorBody),
+        (status = 403, description = "No API token is configured and the server isn't bound to a loopback address", body = ErrorBody),
+        (status = 409, description = "The repo is already being downloaded", body = ErrorBody),
+        (status = 503, description = "The server has no GitHub token", body = ErrorBody),
+    )
+)]
+#[post("/jobs", format = "json", data = "<new>")]
+fn start_job(
+    jobs: &State<Jobs>,
+    access: Result<JobAccess, ApiError>,
+    new: Json<NewJob>,
+) -> Result<Created<Json<JobState>>, ApiError> {
+    access?;
+    let job = jobs.start(new.repo.trim(), new.incremental)?;
+    Ok(Created::new(format!("{BASE}/jobs/{}", job.id)).body(Json(job)))
+}
+
+/// A job and its progress
+#[utoipa::path(
+    get,
+    path = "/api/v1/jobs/{id}",
+    params(("id" = u64, Path)),
+    responses(
+        (status = 200, description = "The job", body = JobState),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+    )
+)]
+#[get("/jobs/<id>", format = "json")]
+fn job(jobs: &State<Jobs>, id: u64) -> Result<Json<JobState>, ApiError> {
+    match jobs.get(id) {
+        Some(job) => Ok(Json(job)),
+        None => Err(NotFound(format!("Job {id}")).into()),
+    }
+}
+
+/// Cancel a running job. Stargazers stored so far are kept.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/jobs/{id}",
+    params(("id" = u64, Path)),
+    responses(
+        (status = 200, description = "The job, cancelled unless it had already finished", body = JobState),
+        (status = 401, description = "Missing or wrong API token", body = ErrorBody),
+        (status = 403, description = "No API token is configured and the server isn't bound to a loopback address", body = ErrorBody),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+    )
+)]
+#[delete("/jobs/<id>")]
+fn cancel_job(
+    jobs: &State<Jobs>,
+    access: Result<JobAccess, ApiError>,
+    id: u64,
+) -> Result<Json<JobState>, ApiError> {
+    access?;
+    match jobs.cancel(id) {
+        Some(job) => Ok(Json(job)),
+        None => Err(NotFound(format!("Job {id}")).into()),
+    }
+}
+
+/// Server-sent events with the job's state each time its progress changes,
+/// ending once it has finished
+#[utoipa::path(
+    get,
+    path = "/api/v1/jobs/{id}/events",
+    params(("id" = u64, Path)),
+    responses(
+        (status = 200, description = "A `job` event per change", content_type = "text/event-stream", body = JobState),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+    )
+)]
+#[get("/jobs/<id>/events")]
+fn job_events(
+    jobs: &State<Jobs>,
+    id: u64,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], ApiError> {
+    let mut job = jobs
+        .subscribe(id)
+        .ok_or_else(|| NotFound(format!("Job {id}")))?;
+
+    Ok(EventStream! {
+        loop {
+            let state = job.borrow_and_update().clone();
+            yield Event::json(&state).event("job");
+            if state.status != JobStatus::Running {
+                break;
+            }
+
+            tokio::select! {
+                changed = job.changed() => if changed.is_err() { break },
+                _ = &mut shutdown => break,
+            }
+        }
+    })
+}
+
+/// Render every error under the API as JSON rather than Rocket's HTML page
+#[catch(default)]
+fn default_catcher(status: Status, _req: &Request) -> ApiError {
+    ApiError::new(status, status.reason().unwrap_or("Unknown error"))
+}
+
+impl PageParams<'_> {
+    fn page(&self) -> Result<Page, ApiError> {
+        page(self.limit, self.offset, self.cursor)
+    }
+}
+
+impl Params<'_> {
+    fn page(&self) -> Result<Page, ApiError> {
+        page(self.limit, self.offset, self.cursor)
+    }
+
+    fn filter(&self) -> Result<Filter, ApiError> {
+        Ok(Filter {
+            repo: self.repo.map(str::to_owned),
+            since: self.since.map(parse_date).transpose()?,
+            until: self.until.map(parse_date).transpose()?,
+        })
+    }
+}
+
+/// The page starting at `cursor`, or at `offset` without one
+fn page(limit: u32, offset: u32, cursor: Option<&str>) -> Result<Page, ApiError> {
+    let offset = match cursor {
+        Some(cursor) => cursor
+            .parse()
+            .map_err(|_| ApiError::bad_request(format!("Invalid cursor: {cursor}")))?,
+        None => offset,
+    };
+    // The next page's offset, and one row past it, must be countable
+    if offset.checked_add(limit + 1).is_none() {
+        return Err(ApiError::bad_request(format!("Offset too large: {offset}")));
+    }
+
+    Ok(Page { limit, offset })
+}
+
+fn parse_date(s: &str) -> Result<DateTime<Utc>, ApiError> {
+    db::parse_date(s).map_err(|e| ApiError::bad_request(e.to_string()))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for EdgeType {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<EdgeType, ApiError> {
+        let kind = request.query_value::<&str>("type").and_then(Result::ok);
+        match kind {
+            None => Outcome::Success(EdgeType("stargazer")),
+            Some(kind) => match EDGE_TYPES.iter().find(|t| **t == kind) {
+                Some(kind) => Outcome::Success(EdgeType(kind)),
+                None => Outcome::Error((
+                    Status::BadRequest,
+                    ApiError::bad_request(format!(
+                        "Invalid type: {kind} (expected one of {})",
+                        EDGE_TYPES.join(", ")
+                    )),
+                )),
+            },
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for JobAccess {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<JobAccess, ApiError> {
+        let token = request
+            .rocket()
+            .state::<ApiToken>()
+            .and_then(|t| t.0.as_deref());
+        let Some(token) = token else {
+            return match request.rocket().config().address.is_loopback() {
+                true => Outcome::Success(JobAccess),
+                false => Outcome::Error((
+                    Status::Forbidden,
+                    ApiError::new(
+                        Status::Forbidden,
+                        "Set server.api_token to manage jobs on a server that isn't bound to a loopback address",
+                    ),
+                )),
+            };
+        };
+
+        let sent = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match sent.is_some_and(|sent| constant_time_eq(sent.as_bytes(), token.as_bytes())) {
+            true => Outcome::Success(JobAccess),
+            false => Outcome::Error((
+                Status::Unauthorized,
+                ApiError::new(Status::Unauthorized, "Missing or wrong API token"),
+            )),
+        }
+    }
+}
+
+/// Compare tokens without revealing how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl ApiError {
+    fn new(status: Status, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> ApiError {
+        ApiError::new(Status::BadRequest, message)
+    }
+}
+
+impl From<NotFound> for ApiError {
+    fn from(e: NotFound) -> ApiError {
+        ApiError::new(Status::NotFound, e.to_string())
+    }
+}
+
+impl From<StartError> for ApiError {
+    fn from(e: StartError) -> ApiError {
+        let status = match e {
+            StartError::NoToken(_) => Status::ServiceUnavailable,
+            StartError::InvalidTarget(_) => Status::BadRequest,
+            StartError::Running(_) => Status::Conflict,
+        };
+        ApiError::new(status, e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> ApiError {
+        if e.downcast_ref::<NotFound>().is_some() {
+            return ApiError::new(Status::NotFound, e.to_string());
+        }
+
+        // Database errors can include SQL, so only the log gets the details
+        eprintln!("API error: {e:#}");
+        ApiError::new(Status::InternalServerError, "Internal server error")
+    }
+}
+
+impl From<form::Errors<'_>> for ApiError {
+    fn from(errors: form::Errors<'_>) -> ApiError {
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|e| match &e.name {
+                Some(name) => format!("{name}: {}", e.kind),
+                None => e.kind.to_string(),
+            })
+            .collect();
+
+        ApiError::bad_request(messages.join("; "))
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let body = Json(ErrorBody {
+            error: ErrorDetail {
+                status: self.status.code,
+                message: self.message,
+            },
+        });
+
+        Response::build_from(body.respond_to(req)?)
+            .status(self.status)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use utoipa::OpenApi;
+
+    use super::{routes, ApiDoc, BASE};
+
+    #[test]
+    fn spec_matches_routes() {
+        let registered: BTreeSet<(String, String)> = routes()
+            .iter()
+            .map(|r| {
+                // Rocket's `<name>` segments are `{name}` in OpenAPI
+                let path = r
+                    .uri
+                    .path()
+                    .replace('<', "{")
+                    .replace('>', "}")
+                    .replace("..}", "}");
+                (r.method.as_str().to_lowercase(), format!("{BASE}{path}"))
+            })
+            .collect();
+
+        let documented: BTreeSet<(String, String)> = ApiDoc::openapi()
+            .paths
+            .paths
+            .into_iter()
+            .flat_map(|(path, item)| {
+                [
+                    ("get", item.get.is_some()),
+                    ("put", item.put.is_some()),
+                    ("post", item.post.is_some()),
+                    ("delete", item.delete.is_some()),
+                    ("patch", item.patch.is_some()),
+                ]
+                .into_iter()
+                .filter(|(_, present)| *present)
+                .map(move |(method, _)| (method.to_owned(), path.clone()))
+            })
+            .collect();
+
+        assert_eq!(registered, documented);
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Result;
+
+use crate::db::{self, Filter, Page};
+
+/// Print a table of stargazer cohorts and how many of each went on to engage
+/// with the repo
+pub async fn cohorts(db_url: &str, repo: Option<&str>) -> Result<()> {
+    let db = db::connect(db_url).await?;
+
+    println!(
+        "{:<8} {:>10} {:>16} {:>16} {:>16} {:>16}",
+        "Cohort", "Stargazers", "Watchers", "Forkers", "Issue authors", "Contributors"
+    );
+
+    let filter = Filter {
+        repo: repo.map(str::to_owned),
+        ..Default::default()
+    };
+    let page = Page {
+        limit: u32::MAX,
+        offset: 0,
+    };
+
+    for c in db.get_cohorts(&filter, page).await? {
+        println!(
+            "{:<8} {:>10} {:>16} {:>16} {:>16} {:>16}",
+            c.month,
+            c.stargazers,
+            share(c.watchers, c.stargazers),
+            share(c.forkers, c.stargazers),
+            share(c.issue_authors, c.stargazers),
+            share(c.contributors, c.stargazers),
+        );
+    }
+
+    Ok(())
+}
+
+fn share(count: u32, total: u32) -> String {
+    format!("{count} ({:.1}%)", count as f64 * 100.0 / total as f64)
+}
@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::db;
+
+const MAX_ITERATIONS: usize = 100;
+
+/// Cluster co-starred repositories using the Louvain method and persist the
+/// resulting communities
+pub async fn compute_communities(db_url: &str, min_stars: u32, min_size: usize) -> Result<()> {
+    let db = db::connect(db_url).await?;
+
+    let targets: HashSet<i64> = db.get_targets().await?.into_iter().collect();
+    let stars: Vec<(i64, i64)> = db
+        .get_user_repo_edges()
+        .await?
+        .into_iter()
+        .filter(|e| e.kind == "stargazer" && !targets.contains(&e.target))
+        .map(|e| (e.source, e.target))
+        .collect();
+
+    // Only cluster repos that enough of our users have starred
+    let mut counts: HashMap<i64, u32> = HashMap::new();
+    for (_, repo) in &stars {
+        *counts.entry(*repo).or_default() += 1;
+    }
+    counts.retain(|_, c| *c >= min_stars);
+
+    let mut by_user: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+    for (user, repo) in &stars {
+        if counts.contains_key(repo) {
+            by_user.entry(*user).or_default().push(*repo);
+        }
+    }
+
+    let graph = costar_graph(by_user.values());
+    let labels = louvain(&graph);
+
+    let mut clusters: HashMap<usize, Vec<i64>> = HashMap::new();
+    for (repo, label) in labels {
+        clusters.entry(label).or_default().push(repo);
+    }
+
+    let mut communities: Vec<(u32, Vec<(i64, u32)>)> = clusters
+        .into_values()
+        .filter(|repos| repos.len() >= min_size)
+        .map(|repos| {
+            let community: HashSet<i64> = repos.iter().copied().collect();
+            let members = by_user
+                .values()
+                .filter(|starred| starred.iter().any(|r| community.contains(r)))
+                .count();
+
+            let mut repos: Vec<(i64, u32)> = repos.into_iter().map(|r| (r, counts[&r])).collect();
+            repos.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+            (members as u32, repos)
+        })
+        .collect();
+    communities.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.len().cmp(&a.1.len())));
+
+    db.set_communities(&communities).await?;
+    println!("Found {} communities", communities.len());
+
+    Ok(())
+}
+
+/// Build a weighted repo-repo graph, where each user who starred both repos
+/// adds `1 / (n - 1)` to the edge, `n` being the number of repos they starred.
+/// This stops a handful of prolific stargazers from binding everything together.
+fn costar_graph<'a, I>(starred: I) -> HashMap<i64, HashMap<i64, f64>>
+where
+    I: Iterator<Item = &'a Vec<i64>>,
+{
+    let mut graph: HashMap<i64, HashMap<i64, f64>> = HashMap::new();
+
+    for repos in starred {
+        if repos.len() < 2 {
+            continue;
+        }
+
+        let weight = 1.0 / (repos.len() - 1) as f64;
+        for (i, a) in repos.iter().enumerate() {
+            for b in &repos[i + 1..] {
+                *graph.entry(*a).or_default().entry(*b).or_default() += weight;
+                *graph.entry(*b).or_default().entry(*a).or_default() += weight;
+            }
+        }
+    }
+
+    graph
+}
+
+/// Louvain modularity optimisation: move nodes between communities while it
+/// improves modularity, then collapse each community into a single node and
+/// repeat on the smaller graph until nothing moves.
+fn louvain(graph: &HashMap<i64, HashMap<i64, f64>>) -> HashMap<i64, usize> {
+    let mut nodes: Vec<i64> = graph.keys().copied().collect();
+    nodes.sort_unstable();
+
+    let index: HashMap<i64, usize> = nodes.iter().enumerate().map(|(i, n)| (*n, i)).collect();
+    let mut adjacency: Vec<Vec<(usize, f64)>> = nodes
+        .iter()
+        .map(|n| {
+            let mut edges: Vec<(usize, f64)> =
+                graph[n].iter().map(|(m, w)| (index[m], *w)).collect();
+            edges.sort_by_key(|e| e.0);
+            edges
+        })
+        .collect();
+
+    // Community of each original node
+    let mut membership: Vec<usize> = (0..nodes.len()).collect();
+
+    for _ in 0..MAX_ITERATIONS {
+        let (communities, moved) = local_moves(&adjacency);
+        if !moved {
+            break;
+        }
+
+        for c in membership.iter_mut() {
+            *c = communities[*c];
+        }
+        adjacency = aggregate(&adjacency, &communities);
+    }
+
+    nodes.into_iter().zip(membership).collect()
+}
+
+/// Greedily move each node to the neighbouring community with the best
+/// modularity gain. Returns a dense community index for each node.
+fn local_moves(adjacency: &[Vec<(usize, f64)>]) -> (Vec<usize>, bool) {
+    let n = adjacency.len();
+    let degree: Vec<f64> = adjacency
+        .iter()
+        .map(|edges| edges.iter().map(|(_, w)| w).sum())
+        .collect();
+    let total: f64 = degree.iter().sum();
+
+    let mut community: Vec<usize> = (0..n).collect();
+    let mut community_degree = degree.clone();
+    let mut moved = false;
+
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+
+        for node in 0..n {
+            let current = community[node];
+            community_degree[current] -= degree[node];
+
+            let mut links: BTreeMap<usize, f64> = BTreeMap::new();
+            links.insert(current, 0.0);
+            for (neighbour, weight) in &adjacency[node] {
+                if *neighbour != node {
+                    *links.entry(community[*neighbour]).or_default() += weight;
+                }
+            }
+
+            let gain = |c: usize, w: f64| w - community_degree[c] * degree[node] / total;
+            let mut best = (current, gain(current, links[&current]));
+            for (c, w) in links {
+                let g = gain(c, w);
+                if g > best.1 + 1e-12 {
+                    best = (c, g);
+                }
+            }
+
+            community[node] = best.0;
+            community_degree[best.0] += degree[node];
+            if best.0 != current {
+                changed = true;
+                moved = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    // Renumber communities densely, in order of first appearance
+    let mut dense: HashMap<usize, usize> = HashMap::new();
+    for c in community.iter_mut() {
+        let next = dense.len();
+        *c = *dense.entry(*c).or_insert(next);
+    }
+
+    (community, moved)
+}
+
+/// Collapse each community into a single node, summing the edge weights
+fn aggregate(adjacency: &[Vec<(usize, f64)>], community: &[usize]) -> Vec<Vec<(usize, f64)>> {
+    let size = community.iter().max().map_or(0, |c| c + 1);
+    let mut merged: Vec<BTreeMap<usize, f64>> = vec![BTreeMap::new(); size];
+
+    for (node, edges) in adjacency.iter().enumerate() {
+        for (neighbour, weight) in edges {
+            *merged[community[node]]
+                .entry(community[*neighbour])
+                .or_default() += weight;
+        }
+    }
+
+    merged
+        .into_iter()
+        .map(|edges| edges.into_iter().collect())
+        .collect()
+}
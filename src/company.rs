@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::{
+    db::{self, CompanyCount, Filter, Page, Storage},
+    github::GitHub,
+};
+
+/// Legal suffixes that don't distinguish one employer from another
+const LEGAL_SUFFIXES: &[&str] = &[
+    "inc",
+    "incorporated",
+    "llc",
+    "llp",
+    "ltd",
+    "limited",
+    "gmbh",
+    "corp",
+    "corporation",
+    "co",
+    "company",
+    "ag",
+    "sa",
+    "sas",
+    "sarl",
+    "srl",
+    "spa",
+    "bv",
+    "nv",
+    "plc",
+    "pty",
+    "oy",
+    "ab",
+    "as",
+    "aps",
+    "kk",
+    "pvt",
+    "private",
+];
+
+/// Turns free text profile companies ("@google", "Google LLC", "google") into
+/// a comparable key and a display name
+pub struct Normaliser {
+    aliases: HashMap<String, String>,
+    orgs: HashMap<String, String>,
+}
+
+impl Normaliser {
+    pub fn new(aliases: Vec<(String, String)>, orgs: Vec<(String, String)>) -> Normaliser {
+        Normaliser {
+            aliases: aliases
+                .into_iter()
+                .map(|(alias, company)| (key(&alias), company))
+                .collect(),
+            orgs: orgs
+                .into_iter()
+                .map(|(login, name)| (login.to_lowercase(), name))
+                .collect(),
+        }
+    }
+
+    /// Return the grouping key and display name for a company, or `None` if
+    /// nothing meaningful is left after cleaning
+    pub fn normalise(&self, company: &str) -> Option<(String, String)> {
+        // Only the first of several employers counts, e.g. "@github, @microsoft"
+        let first = company
+            .split([',', '/', '|', ';'])
+            .flat_map(|s| s.split(" & "))
+            .map(str::trim)
+            .find(|s| !s.is_empty())?;
+
+        let name = match first.strip_prefix('@') {
+            Some(login) => self
+                .orgs
+                .get(&login.to_lowercase())
+                .cloned()
+                .unwrap_or_else(|| login.to_owned()),
+            None => first.to_owned(),
+        };
+
+        let name = strip_suffixes(&name);
+        let key = fold(&name);
+        if key.is_empty() {
+            return None;
+        }
+
+        match self.aliases.get(&key) {
+            Some(company) => Some((self::key(company), company.clone())),
+            None => Some((key, name)),
+        }
+    }
+}
+
+/// Grouping key for a name, as [`Normaliser::normalise`] builds it, so that
+/// aliases such as "Google LLC" match what is left of "Google, LLC."
+fn key(name: &str) -> String {
+    fold(&strip_suffixes(name))
+}
+
+/// Case fold and drop punctuation, e.g. "Google, Inc." becomes "google inc"
+fn fold(s: &str) -> String {
+    s.trim_start_matches('@')
+        .to_lowercase()
+        .split(|c: char| !(c.is_alphanumeric() || c == '&' || c == '+'))
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Remove trailing legal suffixes while keeping the original casing, e.g.
+/// "Acme Co., Ltd." becomes "Acme"
+fn strip_suffixes(name: &str) -> String {
+    let mut words: Vec<&str> = name.split_whitespace().collect();
+
+    while words.len() > 1 {
+        let last = fold(words[words.len() - 1]).replace(' ', "");
+        if LEGAL_SUFFIXES.contains(&last.as_str()) {
+            words.pop();
+        } else {
+            break;
+        }
+    }
+
+    words.join(" ").trim_end_matches([',', '.', ' ']).to_owned()
+}
+
+/// Rank companies by how many stargazers of `repo` (or of any target repo)
+/// work there
+pub async fn top_companies(
+    db: &dyn Storage,
+    filter: &Filter,
+    page: Page,
+) -> Result<Vec<CompanyCount>> {
+    let normaliser = Normaliser::new(
+        db.get_company_aliases().await?,
+        db.get_organizations().await?,
+    );
+    let companies = db.get_stargazer_companies(filter).await?;
+
+    let ranked = rank(&normaliser, companies)
+        .into_iter()
+        .map(|(company, count)| CompanyCount { company, count })
+        .collect();
+    Ok(page.slice(ranked))
+}
+
+/// Rank companies by how many stargazers work there. The display name for
+/// each company is its alias, or else its most common spelling.
+pub fn rank(normaliser: &Normaliser, companies: Vec<String>) -> Vec<(String, u32)> {
+    let mut groups: HashMap<String, HashMap<String, u32>> = HashMap::new();
+    for company in companies {
+        if let Some((key, name)) = normaliser.normalise(&company) {
+            *groups.entry(key).or_default().entry(name).or_default() += 1;
+        }
+    }
+
+    let mut ranked: Vec<(String, u32)> = groups
+        .into_values()
+        .map(|names| {
+            let total = names.values().sum();
+            let (name, _) = names
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+                .expect("groups are never empty");
+            (name, total)
+        })
+        .collect();
+
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    ranked
+}
+
+pub async fn add_alias(db_url: &str, alias: &str, company: &str) -> Result<()> {
+    let db = db::connect(db_url).await?;
+    db.add_company_alias(alias, company).await
+}
+
+pub async fn remove_alias(db_url: &str, alias: &str) -> Result<()> {
+    let db = db::connect(db_url).await?;
+    db.remove_company_alias(alias).await
+}
+
+pub async fn list_aliases(db_url: &str) -> Result<()> {
+    let db = db::connect(db_url).await?;
+    for (alias, company) in db.get_company_aliases().await? {
+        println!("{alias} => {company}");
+    }
+    Ok(())
+}
+
+/// Look up any "@org" mentions in a company that we haven't seen before
+pub async fn resolve_orgs(db: &dyn Storage, github: &GitHub, company: &str) -> Result<()> {
+    for word in company.split(|c: char| c.is_whitespace() || c == ',') {
+        let Some(login) = word.strip_prefix('@') else {
+            continue;
+        };
+        let login = login.trim_end_matches(|c: char| !(c.is_alphanumeric() || c == '-'));
+        if login.is_empty() || db.has_organization(login).await? {
+            continue;
+        }
+
+        let org = github.get_organization(login).await?;
+        db.add_organization(login, org.and_then(|o| o.name).as_deref())
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normaliser(aliases: &[(&str, &str)]) -> Normaliser {
+        let owned = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(a, b)| (a.to_string(), b.to_string()))
+                .collect()
+        };
+        Normaliser::new(owned(aliases), owned(&[("google", "Google")]))
+    }
+
+    #[test]
+    fn folds_case_and_punctuation() {
+        assert_eq!(fold("Google, Inc."), "google inc");
+        assert_eq!(fold("@AT&T"), "at&t");
+        assert_eq!(fold("  "), "");
+    }
+
+    #[test]
+    fn strips_legal_suffixes() {
+        assert_eq!(strip_suffixes("Acme Co., Ltd."), "Acme");
+        assert_eq!(strip_suffixes("Google LLC"), "Google");
+        assert_eq!(strip_suffixes("Siemens AG"), "Siemens");
+        // A suffix on its own is the whole name
+        assert_eq!(strip_suffixes("Company"), "Company");
+    }
+
+    #[test]
+    fn groups_spellings_of_a_company() {
+        let normaliser = normaliser(&[]);
+        for company in ["@google", "Google LLC", "google", "Google, Inc."] {
+            assert_eq!(
+                normaliser.normalise(company).map(|(key, _)| key).as_deref(),
+                Some("google"),
+                "{company:?}"
+            );
+        }
+        // Organisations are shown by their name on GitHub
+        assert_eq!(
+            normaliser
+                .normalise("@google")
+                .map(|(_, name)| name)
+                .as_deref(),
+            Some("Google")
+        );
+        assert_eq!(
+            normaliser.normalise("@unknown-org"),
+            Some(("unknown org".to_owned(), "unknown-org".to_owned()))
+        );
+        assert_eq!(normaliser.normalise(" , "), None);
+    }
+
+    #[test]
+    fn takes_the_first_of_several_employers() {
+        let normaliser = normaliser(&[]);
+        for company in [
+            "@github, @microsoft",
+            "GitHub / Microsoft",
+            "GitHub & Microsoft",
+        ] {
+            assert_eq!(
+                normaliser.normalise(company).map(|(key, _)| key).as_deref(),
+                Some("github"),
+                "{company:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn applies_aliases() {
+        let normaliser = normaliser(&[("Google LLC", "Alphabet Inc."), ("fb", "Meta")]);
+        for company in ["@google", "Google LLC", "google", "Alphabet"] {
+            assert_eq!(
+                normaliser.normalise(company),
+                match company {
+                    "Alphabet" => Some(("alphabet".to_owned(), "Alphabet".to_owned())),
+                    _ => Some(("alphabet".to_owned(), "Alphabet Inc.".to_owned())),
+                },
+                "{company:?}"
+            );
+        }
+        assert_eq!(
+            normaliser.normalise("FB").map(|(_, name)| name).as_deref(),
+            Some("Meta")
+        );
+
+        let companies = ["@google", "Alphabet", "Google LLC", "Acme"];
+        let ranked = rank(&normaliser, companies.map(str::to_owned).to_vec());
+        assert_eq!(
+            ranked,
+            [("Alphabet Inc.".to_owned(), 3), ("Acme".to_owned(), 1)]
+        );
+    }
+}
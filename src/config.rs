@@ -0,0 +1,273 @@
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use rocket::figment::{
+    providers::{Env, Format, Serialized, Toml},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+
+const FILE_NAME: &str = "stargazers.toml";
+
+/// Settings for every subcommand, layered from (lowest priority first)
+/// built-in defaults, the user's config file, `./stargazers.toml`, a file
+/// passed with `--config`, `STARGAZERS_*` env vars and CLI flags
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    /// SQLite database path or postgres:// URL to read and write
+    pub db: String,
+    /// Repos (owner/name) that `download` fetches when none is given
+    pub targets: Vec<String>,
+    pub github: GitHubConfig,
+    pub crawl: CrawlConfig,
+    pub retry: RetryConfig,
+    pub server: ServerConfig,
+    pub sync: SyncConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct GitHubConfig {
+    pub url: String,
+    pub user_agent: String,
+    /// Items requested per page of a list endpoint, from 1 to 100
+    pub page_size: u32,
+    /// Personal access token. Prefer `token_command` or `token_env` to keep
+    /// it out of the config file.
+    pub token: Option<String>,
+    /// Shell command that prints a token, e.g. `gh auth token`
+    pub token_command: Option<String>,
+    /// Env var holding a token
+    pub token_env: String,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Profile location, company and bio of new stargazers
+    Profiles,
+    /// Users each stargazer follows
+    Following,
+    /// Repos each stargazer has starred
+    Starred,
+    /// Repos each stargazer watches
+    Subscribed,
+    /// Forks of the target repo
+    Forks,
+    /// Issues opened on the target repo
+    Issues,
+    /// Contributors to the target repo
+    Contributors,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct CrawlConfig {
+    /// Which parts of the crawl to run, after listing the stargazers
+    pub stages: Vec<Stage>,
+    /// How many stargazers to fetch at once
+    pub concurrency: usize,
+    /// Only download new stargazers
+    pub quick: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Attempts per request before giving up
+    pub attempts: u32,
+    /// Delay before the first retry, doubling after each attempt up to ten
+    /// minutes
+    pub backoff_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    /// Bearer token required to start and cancel downloads. Without one,
+    /// they are only accepted when `address` is a loopback address.
+    pub api_token: Option<String>,
+    /// Origin allowed to call the API from another page in debug builds,
+    /// e.g. the frontend's dev server
+    pub cors_origin: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct SyncConfig {
+    /// Hours between refreshes of the targets by `watch`
+    pub interval_hours: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            db: "db/stargazers.sqlite".to_string(),
+            targets: Vec::new(),
+            github: GitHubConfig::default(),
+            crawl: CrawlConfig::default(),
+            retry: RetryConfig::default(),
+            server: ServerConfig::default(),
+            sync: SyncConfig::default(),
+        }
+    }
+}
+
+impl Default for GitHubConfig {
+    fn default() -> GitHubConfig {
+        GitHubConfig {
+            url: "https://api.github.com".into(),
+            user_agent: "Stargazer Gazer".into(),
+            page_size: 100,
+            token: None,
+            token_command: None,
+            token_env: "GITHUB_TOKEN".into(),
+        }
+    }
+}
+
+impl Default for CrawlConfig {
+    fn default() -> CrawlConfig {
+        CrawlConfig {
+            stages: vec![
+                Stage::Profiles,
+                Stage::Following,
+                Stage::Starred,
+                Stage::Subscribed,
+                Stage::Forks,
+                Stage::Issues,
+                Stage::Contributors,
+            ],
+            concurrency: 4,
+            quick: false,
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> RetryConfig {
+        RetryConfig {
+            attempts: 5,
+            backoff_ms: 1000,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port: 8080,
+            api_token: None,
+            cors_origin: "http://localhost:5173".into(),
+        }
+    }
+}
+
+impl Default for SyncConfig {
+    fn default() -> SyncConfig {
+        SyncConfig { interval_hours: 6 }
+    }
+}
+
+impl Config {
+    /// Layer the config files and env vars over the defaults. CLI flags are
+    /// merged on top by the caller.
+    pub fn figment(file: Option<&Path>) -> Result<Figment> {
+        if let Some(file) = file.filter(|f| !f.exists()) {
+            bail!("Config file not found: {}", file.display());
+        }
+
+        let mut figment = Figment::from(Serialized::defaults(Config::default()));
+        for path in Config::files(file) {
+            figment = figment.merge(Toml::file(path));
+        }
+        Ok(figment.merge(Env::prefixed("STARGAZERS_").split("__")))
+    }
+
+    /// Config files in the order they are applied
+    pub fn files(file: Option<&Path>) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+
+        let user_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        if let Some(dir) = user_dir {
+            files.push(dir.join("stargazers").join(FILE_NAME));
+        }
+
+        files.push(PathBuf::from(FILE_NAME));
+        files.extend(file.map(Path::to_path_buf));
+        files
+    }
+
+    /// Find a GitHub token: from the config itself, then `token_command`,
+    /// then the `token_env` env var
+    pub fn token(&self) -> Result<String> {
+        if let Some(token) = &self.github.token {
+            return Ok(token.clone());
+        }
+
+        if let Some(command) = &self.github.token_command {
+            let output = process::Command::new("sh")
+                .args(["-c", command])
+                .output()
+                .with_context(|| format!("Failed to run token command: {command}"))?;
+            if !output.status.success() {
+                bail!("Token command failed: {command}");
+            }
+            return Ok(String::from_utf8(output.stdout)?.trim().to_owned());
+        }
+
+        env::var(&self.github.token_env).map_err(|_| {
+            anyhow!(
+                "No GitHub token: pass --pat, set {} or configure github.token_command",
+                self.github.token_env
+            )
+        })
+    }
+}
+
+impl SyncConfig {
+    /// Time between refreshes, failing unless `interval_hours` is at least
+    /// one and small enough to count in seconds
+    pub fn interval(&self) -> Result<Duration> {
+        ensure!(
+            self.interval_hours > 0,
+            "sync.interval_hours must be at least 1"
+        );
+        let secs = self.interval_hours.checked_mul(60 * 60).with_context(|| {
+            format!("sync.interval_hours is too large: {}", self.interval_hours)
+        })?;
+        Ok(Duration::from_secs(secs))
+    }
+}
+
+/// Print the effective config as TOML, with any token hidden, preceded by
+/// the config files that were found
+pub fn show_config(config: &Config, file: Option<&Path>) -> Result<()> {
+    for path in Config::files(file).iter().filter(|p| p.exists()) {
+        println!("# Loaded {}", path.display());
+    }
+
+    let mut value = toml::Value::try_from(config)?;
+    for (section, key) in [("github", "token"), ("server", "api_token")] {
+        if let Some(token) = value
+            .get_mut(section)
+            .and_then(|section| section.get_mut(key))
+        {
+            *token = toml::Value::String("********".into());
+        }
+    }
+    print!("{}", toml::to_string_pretty(&value)?);
+    Ok(())
+}
@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use sqlx::{Database, Encode, QueryBuilder, Type};
+
+use super::timestamp;
+use crate::geo::Place;
+
+/// Rows per `INSERT`, which keeps the bound parameters well under SQLite's
+/// and PostgreSQL's limits
+const ROWS_PER_INSERT: usize = 1000;
+
+/// Users, repos and edges collected while crawling, which `Storage::write`
+/// stores in one transaction. Users and repos are matched on their GitHub id,
+/// so renames update the stored name, and star dates are replaced. Other
+/// edges that are already stored are left alone.
+#[derive(Default, Debug)]
+pub struct Batch {
+    users: BTreeMap<i64, UserRow>,
+    repos: BTreeMap<i64, RepoRow>,
+    stargazers: BTreeMap<(i64, i64), String>,
+    activity: BTreeMap<(i64, i64, String), Option<String>>,
+    followers: BTreeSet<(i64, i64)>,
+}
+
+#[derive(Default, Debug)]
+struct UserRow {
+    username: String,
+    name: Option<String>,
+    email: Option<String>,
+    location: Option<String>,
+    country: Option<String>,
+    city: Option<String>,
+    company: Option<String>,
+    bio: Option<String>,
+    followers: Option<i64>,
+    /// Whether the profile fields were fetched, so should replace the stored
+    /// ones
+    profile: bool,
+}
+
+#[derive(Debug)]
+struct RepoRow {
+    full_name: String,
+    stargazers: i64,
+    description: Option<String>,
+    language: Option<String>,
+    topics: Vec<String>,
+}
+
+impl Batch {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+            && self.repos.is_empty()
+            && self.stargazers.is_empty()
+            && self.activity.is_empty()
+            && self.followers.is_empty()
+    }
+
+    pub fn add_user(&mut self, id: i64, username: &str, name: Option<&str>, email: Option<&str>) {
+        let user = self.users.entry(id).or_default();
+        user.username = username.to_owned();
+        user.name = user.name.take().or(name.map(str::to_owned));
+        user.email = user.email.take().or(email.map(str::to_owned));
+    }
+
+    /// Attach profile fields to a user added to this batch
+    pub fn set_profile(
+        &mut self,
+        id: i64,
+        location: Option<&str>,
+        place: Option<&Place>,
+        company: Option<&str>,
+        bio: Option<&str>,
+        followers: u32,
+    ) {
+        let user = self.users.entry(id).or_default();
+        user.location = location.map(str::to_owned);
+        user.country = place.map(|p| p.country.clone());
+        user.city = place.and_then(|p| p.city.clone());
+        user.company = company.map(str::to_owned);
+        user.bio = bio.map(str::to_owned);
+        user.followers = Some(followers.into());
+        user.profile = true;
+    }
+
+    pub fn add_repo(
+        &mut self,
+        id: i64,
+        full_name: &str,
+        stargazers: i64,
+        description: Option<&str>,
+        language: Option<&str>,
+        topics: &[String],
+    ) {
+        self.repos.insert(
+            id,
+            RepoRow {
+                full_name: full_name.to_owned(),
+                stargazers,
+                description: description.map(str::to_owned),
+                language: language.map(str::to_owned),
+                topics: topics.to_vec(),
+            },
+        );
+    }
+
+    pub fn add_stargazer(&mut self, user: i64, repo: i64, date: &DateTime<Utc>) {
+        self.stargazers.insert((user, repo), timestamp(date));
+    }
+
+    /// Record that `user` forked, opened an issue on or contributed to `repo`.
+    /// The first date seen is kept.
+    pub fn add_activity(&mut self, user: i64, repo: i64, kind: &str, date: Option<&DateTime<Utc>>) {
+        self.activity
+            .entry((user, repo, kind.to_owned()))
+            .or_insert(date.map(timestamp));
+    }
+
+    pub fn add_subscriber(&mut self, user: i64, repo: i64) {
+        self.add_activity(user, repo, "subscriber", None);
+    }
+
+    pub fn add_follower(&mut self, subject: i64, linked: i64) {
+        self.followers.insert((subject, linked));
+    }
+
+    /// Build the multi-row upserts that store this batch, in an order that
+    /// satisfies the foreign keys
+    pub(super) fn inserts<'a, DB>(&'a self) -> Vec<QueryBuilder<'a, DB>>
+    where
+        DB: Database,
+        i64: Encode<'a, DB> + Type<DB>,
+        String: Encode<'a, DB> + Type<DB>,
+        Option<i64>: Encode<'a, DB> + Type<DB>,
+        Option<String>: Encode<'a, DB> + Type<DB>,
+    {
+        let mut inserts = Vec::new();
+        let now = timestamp(&Utc::now());
+
+        let users: Vec<_> = self.users.iter().collect();
+        for chunk in users.chunks(ROWS_PER_INSERT) {
+            inserts.push(release_names(
+                r#""user""#,
+                "username",
+                chunk.iter().map(|(id, user)| (**id, &user.username)),
+            ));
+
+            for profile in [false, true] {
+                let rows: Vec<_> = chunk.iter().filter(|(_, u)| u.profile == profile).collect();
+                if rows.is_empty() {
+                    continue;
+                }
+
+                let mut qb = QueryBuilder::new(
+                    r#"INSERT INTO "user" (id, username, name, email, location, country, city, company, bio, followers, profiled_at, last_seen_at, created_at, updated_at) "#,
+                );
+                qb.push_values(rows, |mut row, (id, user)| {
+                    row.push_bind(**id)
+                        .push_bind(user.username.clone())
+                        .push_bind(user.name.clone())
+                        .push_bind(user.email.clone())
+                        .push_bind(user.location.clone())
+                        .push_bind(user.country.clone())
+                        .push_bind(user.city.clone())
+                        .push_bind(user.company.clone())
+                        .push_bind(user.bio.clone())
+                        .push_bind(user.followers)
+                        .push_bind(user.profile.then(|| now.clone()))
+                        .push_bind(now.clone())
+                        .push_bind(now.clone())
+                        .push_bind(now.clone());
+                });
+
+                // Downloaded values replace imported ones
+                let mut columns = vec![("username", "excluded.username"), ("import", "NULL")];
+                if profile {
+                    columns.extend([
+                        ("name", "excluded.name"),
+                        ("email", "excluded.email"),
+                        ("location", "excluded.location"),
+                        ("country", "excluded.country"),
+                        ("city", "excluded.city"),
+                        ("company", "excluded.company"),
+                        ("bio", "excluded.bio"),
+                        ("followers", "excluded.followers"),
+                        ("profiled_at", "excluded.profiled_at"),
+                    ]);
+                } else {
+                    // Lists of users leave out name and email, so only a
+                    // profile can clear them
+                    columns.extend([
+                        ("name", r#"coalesce(excluded.name, "user".name)"#),
+                        ("email", r#"coalesce(excluded.email, "user".email)"#),
+                    ]);
+                }
+                qb.push(format!(
+                    " ON CONFLICT (id) DO UPDATE SET {}, last_seen_at = excluded.last_seen_at",
+                    update_set(r#""user""#, &columns)
+                ));
+                inserts.push(qb);
+            }
+
+            inserts.push(record_names(
+                r#"user_name ("user", username, first_seen_at, last_seen_at)"#,
+                r#""user", username"#,
+                chunk.iter().map(|(id, user)| (**id, &user.username)),
+                &now,
+            ));
+        }
+
+        let repos: Vec<_> = self.repos.iter().collect();
+        for chunk in repos.chunks(ROWS_PER_INSERT) {
+            inserts.push(release_names(
+                "repository",
+                "full_name",
+                chunk.iter().map(|(id, repo)| (**id, &repo.full_name)),
+            ));
+
+            let mut qb = QueryBuilder::new(
+                "INSERT INTO repository (id, full_name, stargazers, description, language, last_seen_at, created_at, updated_at) ",
+            );
+            qb.push_values(chunk, |mut row, (id, repo)| {
+                row.push_bind(**id)
+                    .push_bind(repo.full_name.clone())
+                    .push_bind(repo.stargazers)
+                    .push_bind(repo.description.clone())
+                    .push_bind(repo.language.clone())
+                    .push_bind(now.clone())
+                    .push_bind(now.clone())
+                    .push_bind(now.clone());
+            });
+            qb.push(format!(
+                " ON CONFLICT (id) DO UPDATE SET {}, last_seen_at = excluded.last_seen_at",
+                update_set(
+                    "repository",
+                    &[
+                        ("full_name", "excluded.full_name"),
+                        ("stargazers", "excluded.stargazers"),
+                        ("description", "excluded.description"),
+                        ("language", "excluded.language"),
+                        ("import", "NULL"),
+                    ]
+                )
+            ));
+            inserts.push(qb);
+
+            inserts.push(record_names(
+                "repository_name (repository, full_name, first_seen_at, last_seen_at)",
+                "repository, full_name",
+                chunk.iter().map(|(id, repo)| (**id, &repo.full_name)),
+                &now,
+            ));
+        }
+
+        let topics: Vec<_> = self
+            .repos
+            .iter()
+            .flat_map(|(id, repo)| repo.topics.iter().map(move |topic| (*id, topic)))
+            .collect();
+        for chunk in topics.chunks(ROWS_PER_INSERT) {
+            let mut qb = QueryBuilder::new("INSERT INTO repository_topic (repository, topic) ");
+            qb.push_values(chunk, |mut row, (id, topic)| {
+                row.push_bind(*id).push_bind((*topic).clone());
+            });
+            qb.push(" ON CONFLICT DO NOTHING");
+            inserts.push(qb);
+        }
+
+        let stargazers: Vec<_> = self.stargazers.iter().collect();
+        for chunk in stargazers.chunks(ROWS_PER_INSERT) {
+            let mut qb = QueryBuilder::new(
+                r#"INSERT INTO user_repos ("user", repository, type, date, created_at, updated_at) "#,
+            );
+            qb.push_values(chunk, |mut row, ((user, repo), date)| {
+                row.push_bind(*user)
+                    .push_bind(*repo)
+                    .push_bind("stargazer".to_owned())
+                    .push_bind((*date).clone())
+                    .push_bind(now.clone())
+                    .push_bind(now.clone());
+            });
+            qb.push(format!(
+                r#" ON CONFLICT ("user", repository, type) DO UPDATE SET {}"#,
+                update_set(
+                    "user_repos",
+                    &[("date", "excluded.date"), ("import", "NULL")]
+                )
+            ));
+            inserts.push(qb);
+        }
+
+        let activity: Vec<_> = self.activity.iter().collect();
+        for chunk in activity.chunks(ROWS_PER_INSERT) {
+            let mut qb = QueryBuilder::new(
+                r#"INSERT INTO user_repos ("user", repository, type, date, created_at, updated_at) "#,
+            );
+            qb.push_values(chunk, |mut row, ((user, repo, kind), date)| {
+                row.push_bind(*user)
+                    .push_bind(*repo)
+                    .push_bind(kind.clone())
+                    .push_bind((*date).clone())
+                    .push_bind(now.clone())
+                    .push_bind(now.clone());
+            });
+            qb.push(" ON CONFLICT DO NOTHING");
+            inserts.push(qb);
+        }
+
+        let followers: Vec<_> = self.followers.iter().collect();
+        for chunk in followers.chunks(ROWS_PER_INSERT) {
+            let mut qb =
+                QueryBuilder::new("INSERT INTO user_users (subject, linked, type, created_at) ");
+            qb.push_values(chunk, |mut row, (subject, linked)| {
+                row.push_bind(*subject)
+                    .push_bind(*linked)
+                    .push_bind("follower".to_owned())
+                    .push_bind(now.clone());
+            });
+            qb.push(" ON CONFLICT DO NOTHING");
+            inserts.push(qb);
+        }
+
+        inserts
+    }
+}
+
+/// The `SET` list of an upsert, assigning each column its new value. The
+/// row's `updated_at` is bumped only if one of the values actually changes.
+pub(super) fn update_set(table: &str, columns: &[(&str, &str)]) -> String {
+    let assignments: Vec<String> = columns
+        .iter()
+        .map(|(column, value)| format!("{column} = {value}"))
+        .collect();
+    let changed: Vec<String> = columns
+        .iter()
+        .map(|(column, value)| format!("{value} IS DISTINCT FROM {table}.{column}"))
+        .collect();
+
+    format!(
+        "{}, updated_at = CASE WHEN {} THEN excluded.updated_at ELSE {table}.updated_at END",
+        assignments.join(", "),
+        changed.join(" OR ")
+    )
+}
+
+/// Clear `column` on rows of `table` whose name is being taken by another id,
+/// e.g. when a user renames and someone else signs up with their old login.
+/// The UNIQUE constraint on names would otherwise reject the upsert.
+fn release_names<'a, 'r, DB>(
+    table: &str,
+    column: &str,
+    names: impl Iterator<Item = (i64, &'r String)> + Clone,
+) -> QueryBuilder<'a, DB>
+where
+    DB: Database,
+    i64: Encode<'a, DB> + Type<DB>,
+    String: Encode<'a, DB> + Type<DB>,
+{
+    let mut qb = QueryBuilder::new(format!(
+        "UPDATE {table} SET {column} = NULL WHERE {column} IN ("
+    ));
+    let mut separated = qb.separated(", ");
+    for (_, name) in names.clone() {
+        separated.push_bind(name.clone());
+    }
+    qb.push(format!(") AND (id, {column}) NOT IN (VALUES "));
+    let mut separated = qb.separated(", ");
+    for (id, name) in names {
+        separated
+            .push("(")
+            .push_bind_unseparated(id)
+            .push_unseparated(", ")
+            .push_bind_unseparated(name.clone())
+            .push_unseparated(")");
+    }
+    qb.push(")");
+    qb
+}
+
+/// Add each name to a name history table, or update when it was last seen
+fn record_names<'a, 'r, DB>(
+    table: &str,
+    key: &str,
+    names: impl Iterator<Item = (i64, &'r String)>,
+    seen_at: &str,
+) -> QueryBuilder<'a, DB>
+where
+    DB: Database,
+    i64: Encode<'a, DB> + Type<DB>,
+    String: Encode<'a, DB> + Type<DB>,
+{
+    let mut qb = QueryBuilder::new(format!("INSERT INTO {table} "));
+    qb.push_values(names, |mut row, (id, name)| {
+        row.push_bind(id)
+            .push_bind(name.clone())
+            .push_bind(seen_at.to_owned())
+            .push_bind(seen_at.to_owned());
+    });
+    qb.push(format!(
+        " ON CONFLICT ({key}) DO UPDATE SET last_seen_at = excluded.last_seen_at"
+    ));
+    qb
+}
@@ -0,0 +1,204 @@
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{ColumnIndex, Database, Decode, Row, Type};
+
+use self::ColumnType::{Integer, Text};
+
+/// A table or canned query that can be exported row by row
+#[derive(Clone, Copy, Debug)]
+pub enum Export {
+    Users,
+    Repos,
+    /// Who starred what and when, with names so it can be read on its own
+    Stargazers,
+    /// Every user to repo and user to user edge
+    Edges,
+    Snapshots,
+    /// Repos crawled as targets rather than found through their stargazers
+    Targets,
+    Topics,
+    /// Repos ranked by how many of our users starred them
+    TopRepos,
+    /// Stars per day on each target repo, with a running total
+    StarTimeline,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Text,
+}
+
+/// A value in an exported row
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Text(String),
+}
+
+impl Export {
+    /// The columns of each row, in order
+    pub fn columns(self) -> &'static [(&'static str, ColumnType)] {
+        match self {
+            Export::Users => &[
+                ("id", Integer),
+                ("username", Text),
+                ("name", Text),
+                ("email", Text),
+                ("location", Text),
+                ("country", Text),
+                ("city", Text),
+                ("company", Text),
+                ("bio", Text),
+                ("followers", Integer),
+                ("last_seen_at", Text),
+                ("created_at", Text),
+                ("updated_at", Text),
+            ],
+            Export::Repos => &[
+                ("id", Integer),
+                ("full_name", Text),
+                ("stargazers", Integer),
+                ("description", Text),
+                ("language", Text),
+                ("last_seen_at", Text),
+                ("created_at", Text),
+                ("updated_at", Text),
+            ],
+            Export::Stargazers => &[
+                ("user", Integer),
+                ("username", Text),
+                ("repository", Integer),
+                ("full_name", Text),
+                ("date", Text),
+            ],
+            Export::Edges => &[
+                ("source", Integer),
+                ("target", Integer),
+                ("type", Text),
+                ("date", Text),
+            ],
+            Export::Snapshots => &[
+                ("repository", Integer),
+                ("full_name", Text),
+                ("stargazers", Integer),
+                ("taken_at", Text),
+            ],
+            Export::Targets => &[("repository", Integer), ("full_name", Text)],
+            Export::Topics => &[
+                ("repository", Integer),
+                ("full_name", Text),
+                ("topic", Text),
+            ],
+            Export::TopRepos => &[
+                ("full_name", Text),
+                ("language", Text),
+                ("stargazers", Integer),
+                ("count", Integer),
+            ],
+            Export::StarTimeline => &[
+                ("full_name", Text),
+                ("day", Text),
+                ("stars", Integer),
+                ("total", Integer),
+            ],
+        }
+    }
+
+    /// The query behind the export, which both backends run as is
+    pub(super) fn sql(self) -> &'static str {
+        match self {
+            Export::Users => {
+                r#"SELECT id, username, name, email, location, country, city, company, bio,
+                    followers, last_seen_at, created_at, updated_at
+                FROM "user"
+                ORDER BY id"#
+            }
+            Export::Repos => {
+                "SELECT id, full_name, stargazers, description, language,
+                    last_seen_at, created_at, updated_at
+                FROM repository
+                ORDER BY id"
+            }
+            Export::Stargazers => {
+                r#"SELECT ur."user", u.username, ur.repository, r.full_name, ur.date
+                FROM user_repos ur
+                INNER JOIN "user" u ON (u.id = ur."user")
+                INNER JOIN repository r ON (r.id = ur.repository)
+                WHERE ur.type = 'stargazer'
+                ORDER BY ur.repository, ur.date"#
+            }
+            Export::Edges => {
+                r#"SELECT "user" AS source, repository AS target, type, date
+                FROM user_repos
+                UNION ALL
+                SELECT subject AS source, linked AS target, type, NULL AS date
+                FROM user_users"#
+            }
+            Export::Snapshots => {
+                "SELECT s.repository, r.full_name, s.stargazers, s.taken_at
+                FROM repository_snapshot s
+                INNER JOIN repository r ON (r.id = s.repository)
+                ORDER BY s.repository, s.taken_at"
+            }
+            Export::Targets => {
+                "SELECT t.repository, r.full_name
+                FROM target t
+                INNER JOIN repository r ON (r.id = t.repository)
+                ORDER BY t.repository"
+            }
+            Export::Topics => {
+                "SELECT t.repository, r.full_name, t.topic
+                FROM repository_topic t
+                INNER JOIN repository r ON (r.id = t.repository)
+                ORDER BY t.repository, t.topic"
+            }
+            Export::TopRepos => {
+                "SELECT r.full_name, r.language, r.stargazers, count(*) AS count
+                FROM repository r
+                INNER JOIN user_repos ur ON (r.id = ur.repository)
+                WHERE ur.type = 'stargazer'
+                AND r.full_name IS NOT NULL
+                GROUP BY r.id, r.full_name, r.language, r.stargazers
+                ORDER BY count DESC, r.full_name"
+            }
+            Export::StarTimeline => {
+                "SELECT r.full_name, substr(ur.date, 1, 10) AS day, count(*) AS stars,
+                    CAST(sum(count(*)) OVER (
+                        PARTITION BY r.id ORDER BY substr(ur.date, 1, 10)
+                    ) AS BIGINT) AS total
+                FROM user_repos ur
+                INNER JOIN repository r ON (r.id = ur.repository)
+                WHERE ur.type = 'stargazer'
+                AND ur.date IS NOT NULL
+                AND r.id IN (SELECT repository FROM target)
+                GROUP BY r.id, r.full_name, substr(ur.date, 1, 10)
+                ORDER BY r.full_name, day"
+            }
+        }
+    }
+
+    /// Read a result row into values, following `columns`
+    pub(super) fn decode<R>(self, row: &R) -> Result<Vec<Value>>
+    where
+        R: Row,
+        usize: ColumnIndex<R>,
+        for<'r> i64: Decode<'r, R::Database> + Type<R::Database>,
+        for<'r> String: Decode<'r, R::Database> + Type<R::Database>,
+        R::Database: Database,
+    {
+        self.columns()
+            .iter()
+            .enumerate()
+            .map(|(i, (_, kind))| {
+                let value = match kind {
+                    Integer => row.try_get::<Option<i64>, _>(i)?.map(Value::Integer),
+                    Text => row.try_get::<Option<String>, _>(i)?.map(Value::Text),
+                };
+                Ok(value.unwrap_or(Value::Null))
+            })
+            .collect()
+    }
+}
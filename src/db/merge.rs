@@ -0,0 +1,446 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use sqlx::{query_builder::Separated, Database, Encode, QueryBuilder, Type};
+
+use super::{batch::update_set, timestamp, ColumnType, Export, Value};
+
+/// Rows per `INSERT`, as in `Batch`
+const ROWS_PER_INSERT: usize = 1000;
+
+/// Edge types between two users rather than a user and a repo
+const USER_EDGE_TYPES: &[&str] = &["follower"];
+
+/// Exported users, repos, edges, targets, topics and snapshots from another
+/// crawl, which `Storage::merge` stores in one transaction. Users and repos
+/// are matched on their GitHub id, and the copy seen most recently on GitHub
+/// wins. The rest are added unless already stored, and skipped if a user or
+/// repo they refer to isn't stored.
+#[derive(Debug)]
+pub struct Merge {
+    import: i64,
+    users: BTreeMap<i64, Vec<Value>>,
+    repos: BTreeMap<i64, Vec<Value>>,
+    user_repos: BTreeMap<(i64, i64, String), Option<String>>,
+    user_users: BTreeSet<(i64, i64, String)>,
+    targets: BTreeSet<i64>,
+    topics: BTreeSet<(i64, String)>,
+    /// Repo, stargazers and when the snapshot was taken
+    snapshots: BTreeSet<(i64, i64, String)>,
+}
+
+/// Columns of the users export that are merged, up to `last_seen_at`
+const USER_COLUMNS: usize = 11;
+/// Columns of the repos export that are merged, up to `last_seen_at`
+const REPO_COLUMNS: usize = 6;
+
+impl Merge {
+    /// Start collecting rows for the import with id `import`, which is
+    /// recorded on every row it adds or replaces
+    pub fn new(import: i64) -> Merge {
+        Merge {
+            import,
+            users: BTreeMap::new(),
+            repos: BTreeMap::new(),
+            user_repos: BTreeMap::new(),
+            user_users: BTreeSet::new(),
+            targets: BTreeSet::new(),
+            topics: BTreeSet::new(),
+            snapshots: BTreeSet::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+            + self.repos.len()
+            + self.user_repos.len()
+            + self.user_users.len()
+            + self.targets.len()
+            + self.topics.len()
+            + self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add a row of the users, repos, edges, targets, topics or snapshots
+    /// export
+    pub fn add(&mut self, export: Export, mut row: Vec<Value>) -> Result<()> {
+        let columns = export.columns();
+        if row.len() != columns.len() {
+            bail!("Expected {} columns, found {}", columns.len(), row.len());
+        }
+        for ((name, kind), value) in columns.iter().zip(&row) {
+            match (kind, value) {
+                (_, Value::Null)
+                | (ColumnType::Integer, Value::Integer(_))
+                | (ColumnType::Text, Value::Text(_)) => (),
+                _ => bail!("Unexpected value for {name}: {value:?}"),
+            }
+        }
+
+        match export {
+            Export::Users | Export::Repos => {
+                let Value::Integer(id) = row[0] else {
+                    bail!("Missing id");
+                };
+                let (rows, width) = match export {
+                    Export::Users => (&mut self.users, USER_COLUMNS),
+                    _ => (&mut self.repos, REPO_COLUMNS),
+                };
+                row.truncate(width);
+
+                // Keep the latest copy if an id is repeated
+                let seen = |row: &Vec<Value>| match &row[width - 1] {
+                    Value::Text(seen) => Some(seen.clone()),
+                    _ => None,
+                };
+                if rows.get(&id).is_none_or(|old| seen(&row) >= seen(old)) {
+                    rows.insert(id, row);
+                }
+            }
+            Export::Edges => {
+                let (Value::Integer(source), Value::Integer(target), Value::Text(kind)) =
+                    (&row[0], &row[1], &row[2])
+                else {
+                    bail!("Missing source, target or type");
+                };
+                let key = (*source, *target, kind.clone());
+                if USER_EDGE_TYPES.contains(&kind.as_str()) {
+                    self.user_users.insert(key);
+                } else {
+                    let date = match &row[3] {
+                        Value::Text(date) => Some(date.clone()),
+                        _ => None,
+                    };
+                    self.user_repos.insert(key, date);
+                }
+            }
+            Export::Targets => {
+                let Value::Integer(repo) = row[0] else {
+                    bail!("Missing repository");
+                };
+                self.targets.insert(repo);
+            }
+            Export::Topics => {
+                let (Value::Integer(repo), Value::Text(topic)) = (&row[0], &row[2]) else {
+                    bail!("Missing repository or topic");
+                };
+                self.topics.insert((*repo, topic.clone()));
+            }
+            Export::Snapshots => {
+                let (Value::Integer(repo), Value::Integer(stargazers), Value::Text(taken)) =
+                    (&row[0], &row[2], &row[3])
+                else {
+                    bail!("Missing repository, stargazers or taken_at");
+                };
+                self.snapshots.insert((*repo, *stargazers, taken.clone()));
+            }
+            _ => bail!("Only users, repos, edges, targets, topics and snapshots can be merged"),
+        }
+        Ok(())
+    }
+
+    /// Build the statements that store this merge, in an order that satisfies
+    /// the foreign keys
+    pub(super) fn inserts<'a, DB>(&'a self) -> Vec<QueryBuilder<'a, DB>>
+    where
+        DB: Database,
+        i64: Encode<'a, DB> + Type<DB>,
+        String: Encode<'a, DB> + Type<DB>,
+        Option<i64>: Encode<'a, DB> + Type<DB>,
+        Option<String>: Encode<'a, DB> + Type<DB>,
+    {
+        let mut inserts = Vec::new();
+        let now = timestamp(&Utc::now());
+
+        let tables = [
+            (
+                r#""user""#,
+                "username",
+                "user_name",
+                r#""user""#,
+                &Export::Users.columns()[..USER_COLUMNS],
+                &self.users,
+            ),
+            (
+                "repository",
+                "full_name",
+                "repository_name",
+                "repository",
+                &Export::Repos.columns()[..REPO_COLUMNS],
+                &self.repos,
+            ),
+        ];
+        for (table, name, history, key, columns, rows) in tables {
+            let rows: Vec<_> = rows.values().collect();
+            for chunk in rows.chunks(ROWS_PER_INSERT) {
+                inserts.push(release_names(table, name, columns, chunk));
+                inserts.push(self.upsert(table, name, columns, chunk, &now));
+                inserts.push(record_names(history, key, name, chunk, columns.len()));
+            }
+        }
+
+        let user_repos: Vec<_> = self.user_repos.iter().collect();
+        for chunk in user_repos.chunks(ROWS_PER_INSERT) {
+            let mut qb = QueryBuilder::new(
+                r#"INSERT INTO user_repos ("user", repository, type, date, import, created_at, updated_at)
+                SELECT v.column1, v.column2, v.column3, v.column4, v.column5, v.column6, v.column6
+                FROM ("#,
+            );
+            qb.push_values(chunk, |mut row, ((user, repo, kind), date)| {
+                row.push_bind(*user)
+                    .push_bind(*repo)
+                    .push_bind(kind.clone())
+                    .push_bind((*date).clone())
+                    .push_bind(self.import)
+                    .push_bind(now.clone());
+            });
+            qb.push(
+                r#") AS v
+                WHERE v.column1 IN (SELECT id FROM "user")
+                AND v.column2 IN (SELECT id FROM repository)
+                ON CONFLICT DO NOTHING"#,
+            );
+            inserts.push(qb);
+        }
+
+        let user_users: Vec<_> = self.user_users.iter().collect();
+        for chunk in user_users.chunks(ROWS_PER_INSERT) {
+            let mut qb = QueryBuilder::new(
+                r#"INSERT INTO user_users (subject, linked, type, import, created_at)
+                SELECT v.column1, v.column2, v.column3, v.column4, v.column5
+                FROM ("#,
+            );
+            qb.push_values(chunk, |mut row, (subject, linked, kind)| {
+                row.push_bind(*subject)
+                    .push_bind(*linked)
+                    .push_bind(kind.clone())
+                    .push_bind(self.import)
+                    .push_bind(now.clone());
+            });
+            qb.push(
+                r#") AS v
+                WHERE v.column1 IN (SELECT id FROM "user")
+                AND v.column2 IN (SELECT id FROM "user")
+                ON CONFLICT DO NOTHING"#,
+            );
+            inserts.push(qb);
+        }
+
+        let targets: Vec<_> = self.targets.iter().collect();
+        for chunk in targets.chunks(ROWS_PER_INSERT) {
+            let mut qb =
+                QueryBuilder::new("INSERT INTO target (repository) SELECT v.column1 FROM (");
+            qb.push_values(chunk, |mut row, repo| {
+                row.push_bind(**repo);
+            });
+            qb.push(
+                ") AS v
+                WHERE v.column1 IN (SELECT id FROM repository)
+                ON CONFLICT DO NOTHING",
+            );
+            inserts.push(qb);
+        }
+
+        let topics: Vec<_> = self.topics.iter().collect();
+        for chunk in topics.chunks(ROWS_PER_INSERT) {
+            let mut qb = QueryBuilder::new(
+                "INSERT INTO repository_topic (repository, topic)
+                SELECT v.column1, v.column2 FROM (",
+            );
+            qb.push_values(chunk, |mut row, (repo, topic)| {
+                row.push_bind(*repo).push_bind(topic.clone());
+            });
+            qb.push(
+                ") AS v
+                WHERE v.column1 IN (SELECT id FROM repository)
+                ON CONFLICT DO NOTHING",
+            );
+            inserts.push(qb);
+        }
+
+        // Snapshots have no key, so one taken at the same time as a stored
+        // one is the same snapshot
+        let snapshots: Vec<_> = self.snapshots.iter().collect();
+        for chunk in snapshots.chunks(ROWS_PER_INSERT) {
+            let mut qb = QueryBuilder::new(
+                "INSERT INTO repository_snapshot (repository, stargazers, taken_at)
+                SELECT v.column1, v.column2, v.column3 FROM (",
+            );
+            qb.push_values(chunk, |mut row, (repo, stargazers, taken)| {
+                row.push_bind(*repo)
+                    .push_bind(*stargazers)
+                    .push_bind(taken.clone());
+            });
+            qb.push(
+                ") AS v
+                WHERE v.column1 IN (SELECT id FROM repository)
+                AND NOT EXISTS (
+                    SELECT 1 FROM repository_snapshot s
+                    WHERE s.repository = v.column1 AND s.taken_at = v.column3
+                )",
+            );
+            inserts.push(qb);
+        }
+
+        inserts
+    }
+
+    /// Insert users or repos, replacing stored ones that were last seen
+    /// earlier. A name still held by another row, which was seen more
+    /// recently, is left empty.
+    fn upsert<'a, DB>(
+        &self,
+        table: &str,
+        name: &str,
+        columns: &[(&str, ColumnType)],
+        rows: &[&'a Vec<Value>],
+        now: &str,
+    ) -> QueryBuilder<'a, DB>
+    where
+        DB: Database,
+        i64: Encode<'a, DB> + Type<DB>,
+        String: Encode<'a, DB> + Type<DB>,
+        Option<i64>: Encode<'a, DB> + Type<DB>,
+        Option<String>: Encode<'a, DB> + Type<DB>,
+    {
+        let names: Vec<&str> = columns.iter().map(|(column, _)| *column).collect();
+        let values: Vec<String> = names
+            .iter()
+            .enumerate()
+            .map(|(i, column)| match *column {
+                column if column == name => format!(
+                    "CASE WHEN EXISTS (
+                        SELECT 1 FROM {table} o WHERE o.{name} = v.column{n} AND o.id <> v.column1
+                    ) THEN NULL ELSE v.column{n} END",
+                    n = i + 1
+                ),
+                _ => format!("v.column{}", i + 1),
+            })
+            .collect();
+        let n = columns.len();
+
+        let mut qb = QueryBuilder::new(format!(
+            "INSERT INTO {table} ({}, import, created_at, updated_at)
+            SELECT {}, v.column{}, v.column{}, v.column{}
+            FROM (",
+            names.join(", "),
+            values.join(", "),
+            n + 1,
+            n + 2,
+            n + 2,
+        ));
+        qb.push_values(rows, |mut row, values| {
+            for (value, (_, kind)) in values.iter().zip(columns) {
+                push_value(&mut row, value, *kind);
+            }
+            row.push_bind(self.import).push_bind(now.to_owned());
+        });
+
+        let assignments: Vec<(&str, String)> = names[1..]
+            .iter()
+            .map(|column| (*column, format!("excluded.{column}")))
+            .chain([("import", "excluded.import".to_owned())])
+            .collect();
+        let assignments: Vec<(&str, &str)> = assignments
+            .iter()
+            .map(|(column, value)| (*column, value.as_str()))
+            .collect();
+        qb.push(format!(
+            ") AS v
+            WHERE true
+            ON CONFLICT (id) DO UPDATE SET {}
+            WHERE {table}.last_seen_at IS NULL OR excluded.last_seen_at > {table}.last_seen_at",
+            update_set(table, &assignments)
+        ));
+        qb
+    }
+}
+
+/// Clear names held by other rows that were last seen before the merged row
+/// with that name, as they have since been renamed or taken over
+fn release_names<'a, DB>(
+    table: &str,
+    name: &str,
+    columns: &[(&str, ColumnType)],
+    rows: &[&'a Vec<Value>],
+) -> QueryBuilder<'a, DB>
+where
+    DB: Database,
+    Option<i64>: Encode<'a, DB> + Type<DB>,
+    Option<String>: Encode<'a, DB> + Type<DB>,
+{
+    let mut qb = QueryBuilder::new(format!(
+        "UPDATE {table} SET {name} = NULL WHERE EXISTS (SELECT 1 FROM ("
+    ));
+    qb.push_values(rows, |mut row, values| {
+        for i in [0, 1, columns.len() - 1] {
+            push_value(&mut row, &values[i], columns[i].1);
+        }
+    });
+    qb.push(format!(
+        ") AS v
+        WHERE v.column2 = {table}.{name} AND v.column1 <> {table}.id
+        AND ({table}.last_seen_at IS NULL OR v.column3 > {table}.last_seen_at))"
+    ));
+    qb
+}
+
+/// Add each merged name to a name history table, widening the times it was
+/// seen with that name
+fn record_names<'a, DB>(
+    history: &str,
+    key: &str,
+    name: &str,
+    rows: &[&'a Vec<Value>],
+    seen: usize,
+) -> QueryBuilder<'a, DB>
+where
+    DB: Database,
+    Option<i64>: Encode<'a, DB> + Type<DB>,
+    Option<String>: Encode<'a, DB> + Type<DB>,
+{
+    let mut qb = QueryBuilder::new(format!(
+        "INSERT INTO {history} ({key}, {name}, first_seen_at, last_seen_at)
+        SELECT v.column1, v.column2, v.column3, v.column3
+        FROM ("
+    ));
+    qb.push_values(rows, |mut row, values| {
+        push_value(&mut row, &values[0], ColumnType::Integer);
+        push_value(&mut row, &values[1], ColumnType::Text);
+        push_value(&mut row, &values[seen - 1], ColumnType::Text);
+    });
+    qb.push(format!(
+        ") AS v
+        WHERE v.column2 IS NOT NULL
+        ON CONFLICT ({key}, {name}) DO UPDATE SET
+            first_seen_at = CASE
+                WHEN {history}.first_seen_at IS NULL OR excluded.first_seen_at < {history}.first_seen_at
+                THEN excluded.first_seen_at ELSE {history}.first_seen_at END,
+            last_seen_at = CASE
+                WHEN {history}.last_seen_at IS NULL OR excluded.last_seen_at > {history}.last_seen_at
+                THEN excluded.last_seen_at ELSE {history}.last_seen_at END"
+    ));
+    qb
+}
+
+/// Bind a value with the SQL type of its column, so NULLs are typed too
+fn push_value<'a, DB>(
+    row: &mut Separated<'_, 'a, DB, &'static str>,
+    value: &Value,
+    kind: ColumnType,
+) where
+    DB: Database,
+    Option<i64>: Encode<'a, DB> + Type<DB>,
+    Option<String>: Encode<'a, DB> + Type<DB>,
+{
+    match (kind, value) {
+        (ColumnType::Integer, Value::Integer(i)) => row.push_bind(Some(*i)),
+        (ColumnType::Integer, _) => row.push_bind(None::<i64>),
+        (ColumnType::Text, Value::Text(s)) => row.push_bind(Some(s.clone())),
+        (ColumnType::Text, _) => row.push_bind(None::<String>),
+    };
+}
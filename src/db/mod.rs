@@ -0,0 +1,1160 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use futures::stream::BoxStream;
+use serde::Serialize;
+use sqlx::{migrate::Migrator, Database, Encode, FromRow, QueryBuilder, Type};
+use utoipa::ToSchema;
+
+use crate::geo::Place;
+
+pub use self::{
+    batch::Batch,
+    export::{ColumnType, Export, Value},
+    merge::Merge,
+    postgres::PostgresStorage,
+    sqlite::SqliteStorage,
+};
+
+mod batch;
+mod export;
+mod merge;
+mod postgres;
+mod sqlite;
+
+#[derive(FromRow, Debug)]
+pub struct UserRecord {
+    pub id: i64,
+    /// Cleared when another account has since taken the login
+    pub username: Option<String>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub location: Option<String>,
+    pub country: Option<String>,
+    pub company: Option<String>,
+    pub bio: Option<String>,
+    /// Follower count on GitHub, once the profile has been fetched
+    pub followers: Option<i64>,
+}
+
+#[derive(FromRow, Debug)]
+pub struct RepoRecord {
+    pub id: i64,
+    /// Cleared when another repo has since taken the name
+    pub full_name: Option<String>,
+    pub stargazers: i64,
+    pub description: Option<String>,
+    pub language: Option<String>,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct RepoCount {
+    pub full_name: String,
+    #[sqlx(try_from = "i64")]
+    pub count: u32,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct UserCount {
+    pub login: String,
+    #[sqlx(try_from = "i64")]
+    pub count: u32,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct UserScore {
+    pub login: String,
+    pub score: f64,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct RepoScore {
+    pub full_name: String,
+    pub score: f64,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct TopicScore {
+    pub topic: String,
+    pub score: f64,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct LanguageScore {
+    pub language: String,
+    pub score: f64,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct CountryCount {
+    pub country: String,
+    /// ISO 3166-1 alpha-2 code, for drawing maps
+    #[sqlx(skip)]
+    pub code: Option<String>,
+    #[sqlx(try_from = "i64")]
+    pub count: u32,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct CompanyCount {
+    pub company: String,
+    pub count: u32,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Community {
+    pub id: i64,
+    pub members: u32,
+    pub repos: Vec<RepoCount>,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct Cohort {
+    pub month: String,
+    #[sqlx(try_from = "i64")]
+    pub stargazers: u32,
+    #[sqlx(try_from = "i64")]
+    pub watchers: u32,
+    #[sqlx(try_from = "i64")]
+    pub forkers: u32,
+    #[sqlx(try_from = "i64")]
+    pub issue_authors: u32,
+    #[sqlx(try_from = "i64")]
+    pub contributors: u32,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct RepoEdge {
+    pub full_name: String,
+    pub date: Option<String>,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct UserEdge {
+    pub login: String,
+    pub date: Option<String>,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct UserHit {
+    pub login: String,
+    pub name: Option<String>,
+    pub company: Option<String>,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct RepoHit {
+    pub full_name: String,
+    pub description: Option<String>,
+}
+
+/// A repo's star count on GitHub at the time of a download
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct Snapshot {
+    #[sqlx(try_from = "i64")]
+    pub stargazers: u32,
+    pub taken_at: String,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct UserDetail {
+    pub id: i64,
+    pub login: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub location: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub company: Option<String>,
+    pub bio: Option<String>,
+    /// When the user starred each target repo
+    #[sqlx(skip)]
+    pub target_stars: Vec<RepoEdge>,
+    #[sqlx(skip)]
+    pub starred: Vec<RepoEdge>,
+    #[sqlx(skip)]
+    pub subscribed: Vec<String>,
+    #[sqlx(skip)]
+    pub following: Vec<String>,
+    /// Crawled users who follow this user
+    #[sqlx(skip)]
+    pub followers: Vec<String>,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Debug)]
+pub struct RepoDetail {
+    pub id: i64,
+    pub full_name: String,
+    /// Star count on GitHub when the repo was stored
+    #[sqlx(try_from = "i64")]
+    pub stargazers: u32,
+    pub description: Option<String>,
+    pub language: Option<String>,
+    pub target: bool,
+    #[sqlx(skip)]
+    pub topics: Vec<String>,
+    /// Star counts recorded each time the repo was downloaded as a target
+    #[sqlx(skip)]
+    pub snapshots: Vec<Snapshot>,
+    /// How many of our stargazers also starred this repo
+    #[sqlx(skip)]
+    pub audience_count: u32,
+    /// One page of them, in the order they starred it
+    #[sqlx(skip)]
+    pub audience: Paginated<UserEdge>,
+}
+
+/// A page of one of a target's lists on GitHub, as it was when last fetched
+#[derive(FromRow, Clone, Debug)]
+pub struct Checkpoint {
+    #[sqlx(try_from = "i64")]
+    pub page: u32,
+    pub etag: String,
+    #[sqlx(try_from = "i64")]
+    pub items: u32,
+}
+
+/// Narrows an aggregate down to part of the audience: the users who starred
+/// `repo` (or any target repo) between `since` and `until`
+#[derive(Default, Debug)]
+pub struct Filter {
+    pub repo: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Page {
+    pub limit: u32,
+    pub offset: u32,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub limit: u32,
+    pub offset: u32,
+    /// Pass as `cursor` to fetch the next page. Absent on the last page.
+    /// Currently the next page's offset, in decimal.
+    pub next_cursor: Option<String>,
+}
+
+/// Returned when a requested user or repo isn't in the database
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+#[derive(FromRow, Debug)]
+pub struct EdgeRecord {
+    pub source: i64,
+    pub target: i64,
+    #[sqlx(rename = "type")]
+    pub kind: String,
+    pub date: Option<String>,
+}
+
+/// Everything the commands and the API read from and write to the database,
+/// so they work the same against a local SQLite file or a shared PostgreSQL
+/// server
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // Writes
+    /// Store a batch of crawled users, repos and edges in one transaction
+    async fn write(&self, batch: &Batch) -> Result<()>;
+    /// Record that a database or file is being merged in, and return the id
+    /// that its rows are marked with
+    async fn add_import(&self, source: &str) -> Result<i64>;
+    /// Store a batch of another crawl's users, repos and edges in one
+    /// transaction
+    async fn merge(&self, merge: &Merge) -> Result<()>;
+
+    // Users
+    /// Which of `ids` have had their profile fetched. Users stored only from
+    /// lists, e.g. as someone's followee, haven't.
+    async fn get_profiled_users(&self, ids: &[i64]) -> Result<HashSet<i64>>;
+    async fn set_user_place(&self, id: i64, place: Option<&Place>) -> Result<()>;
+    async fn get_user_id(&self, username: &str) -> Result<Option<i64>>;
+    async fn get_user_locations(&self) -> Result<Vec<(i64, String)>>;
+    async fn get_users(&self) -> Result<Vec<UserRecord>>;
+
+    // Companies
+    async fn has_organization(&self, login: &str) -> Result<bool>;
+    async fn add_organization(&self, login: &str, name: Option<&str>) -> Result<()>;
+    /// GitHub organisations that have a display name
+    async fn get_organizations(&self) -> Result<Vec<(String, String)>>;
+    async fn add_company_alias(&self, alias: &str, company: &str) -> Result<()>;
+    async fn remove_company_alias(&self, alias: &str) -> Result<()>;
+    async fn get_company_aliases(&self) -> Result<Vec<(String, String)>>;
+
+    // Repos
+    async fn add_target(&self, repo: i64) -> Result<()>;
+    async fn get_targets(&self) -> Result<Vec<i64>>;
+    async fn get_repo_id(&self, full_name: &str) -> Result<Option<i64>>;
+    async fn get_repo_names(&self, ids: &[i64]) -> Result<HashMap<i64, String>>;
+    async fn get_repos(&self) -> Result<Vec<RepoRecord>>;
+    /// Topics of every repo that has any, in alphabetical order
+    async fn get_repo_topics(&self) -> Result<HashMap<i64, Vec<String>>>;
+
+    // Edges
+    async fn get_user_repo_edges(&self) -> Result<Vec<EdgeRecord>>;
+    async fn get_user_user_edges(&self) -> Result<Vec<EdgeRecord>>;
+
+    // Exports
+    /// Stream the rows of an export, with values in the order of its columns
+    fn export(&self, export: Export) -> BoxStream<'_, Result<Vec<Value>>>;
+
+    // Snapshots
+    async fn add_snapshot(&self, repo: i64, stargazers: i64) -> Result<()>;
+    async fn get_snapshots(&self, repo: i64) -> Result<Vec<Snapshot>>;
+
+    // Syncs
+    /// Pages of a target's `list` (e.g. stargazers) fetched by earlier
+    /// downloads
+    async fn get_checkpoints(&self, repo: i64, list: &str) -> Result<Vec<Checkpoint>>;
+    async fn set_checkpoints(&self, repo: i64, list: &str, pages: &[Checkpoint]) -> Result<()>;
+    /// Record that a sync has started, and return its id
+    async fn start_sync_run(&self) -> Result<i64>;
+    async fn finish_sync_run(
+        &self,
+        id: i64,
+        status: &str,
+        duration_ms: i64,
+        requests: i64,
+        error: Option<&str>,
+    ) -> Result<()>;
+    /// Mark syncs that never finished, as the process running them stopped
+    async fn interrupt_sync_runs(&self) -> Result<()>;
+
+    // Computed results
+    async fn set_user_influence(&self, scores: &[(i64, f64)]) -> Result<()>;
+    async fn set_communities(&self, communities: &[(u32, Vec<(i64, u32)>)]) -> Result<()>;
+
+    // Aggregates
+    /// Number of our users who starred each repo
+    async fn get_star_counts(&self) -> Result<Vec<(i64, u32)>>;
+    /// For each repo starred by `user`, count how many users also starred
+    /// each repo that `user` hasn't starred. Target repos are left out, as
+    /// every stargazer has starred them.
+    async fn get_costarred_for_user(&self, user: i64) -> Result<Vec<(i64, i64, u32)>>;
+    /// Count how many users starred both `repo` and each other repo
+    async fn get_costarred_for_repo(&self, repo: i64) -> Result<Vec<(i64, u32)>>;
+    /// Rank repos by how many users have a `kind` edge (e.g. stargazer) to them
+    async fn get_top_repos(
+        &self,
+        kind: &str,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<RepoCount>>;
+    async fn get_top_following(&self, filter: &Filter, page: Page) -> Result<Vec<UserCount>>;
+    async fn get_influential_stargazers(
+        &self,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<UserScore>>;
+    async fn get_communities(&self, page: Page, repos: u32) -> Result<Vec<Community>>;
+    /// Rank topics of the repos our stargazers have starred. Each user's
+    /// stars share a total weight of 1, so prolific stargazers don't drown
+    /// out everyone else. Target repos are excluded as every stargazer has
+    /// starred them.
+    async fn get_top_topics(&self, filter: &Filter, page: Page) -> Result<Vec<TopicScore>>;
+    /// Rank primary languages of the repos our stargazers have starred,
+    /// weighted per user in the same way as [`Storage::get_top_topics`]
+    async fn get_top_languages(&self, filter: &Filter, page: Page) -> Result<Vec<LanguageScore>>;
+    async fn get_stargazer_countries(
+        &self,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<CountryCount>>;
+    async fn get_stargazer_companies(&self, filter: &Filter) -> Result<Vec<String>>;
+    /// Group stargazers by the month they starred `repo` (or any target
+    /// repo), and count how many went on to watch, fork, open issues or
+    /// contribute
+    async fn get_cohorts(&self, filter: &Filter, page: Page) -> Result<Vec<Cohort>>;
+    async fn get_user_detail(&self, username: &str) -> Result<Option<UserDetail>>;
+    /// Stored metadata for a repo, plus which of the audience picked out by
+    /// `filter` starred it
+    async fn get_repo_detail(
+        &self,
+        full_name: &str,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Option<RepoDetail>>;
+    /// Users whose login, name, company or bio contain every word as a prefix
+    async fn search_users(&self, words: &[String], page: Page) -> Result<Vec<UserHit>>;
+    /// Repos whose name, description or topics contain every word as a prefix
+    async fn search_repos(&self, words: &[String], page: Page) -> Result<Vec<RepoHit>>;
+}
+
+/// Open the database at `url`, which is either a `postgres://` URL or the
+/// path to a SQLite file, and bring its schema up to date
+pub async fn connect(url: &str) -> Result<Box<dyn Storage>> {
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        Ok(Box::new(PostgresStorage::open(url).await?))
+    } else {
+        let path = url.strip_prefix("sqlite://").unwrap_or(url);
+        Ok(Box::new(SqliteStorage::open(path).await?))
+    }
+}
+
+/// Open an existing database at `url` without writing to it or changing its
+/// schema, e.g. to read another database from. Fails if the schema is older
+/// than this version expects.
+pub async fn connect_read_only(url: &str) -> Result<Box<dyn Storage>> {
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        Ok(Box::new(PostgresStorage::open_read_only(url).await?))
+    } else {
+        let path = url.strip_prefix("sqlite://").unwrap_or(url);
+        Ok(Box::new(SqliteStorage::open_read_only(path).await?))
+    }
+}
+
+/// Check that a database at migration `version` has every migration this
+/// version expects
+fn check_schema(version: Option<i64>, migrator: &Migrator) -> Result<()> {
+    let latest = migrator.iter().map(|m| m.version).max().unwrap_or(0);
+    match version {
+        Some(version) if version >= latest => Ok(()),
+        Some(version) => Err(anyhow!(
+            "Database schema is at version {version}, expected {latest}. Run a command with `--db` pointing at it to migrate it first."
+        )),
+        None => Err(anyhow!("Database has no schema")),
+    }
+}
+
+/// Format a time for storage as RFC 3339 in UTC with whole seconds, e.g.
+/// `2024-01-31T12:00:00Z`. Every stored time has the same width, so they sort
+/// and compare correctly as text.
+pub(crate) fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Accept either a plain date (`2024-01-31`) or an RFC 3339 timestamp
+pub fn parse_date(s: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+
+    DateTime::parse_from_rfc3339(s)
+        .map(|d| d.with_timezone(&Utc))
+        .map_err(|_| anyhow!("Invalid date: {s}"))
+}
+
+impl Filter {
+    fn is_empty(&self) -> bool {
+        self.repo.is_none() && self.since.is_none() && self.until.is_none()
+    }
+
+    /// Append `AND <column> IN (<audience>)` to a query
+    fn push_audience<'a, DB>(&self, qb: &mut QueryBuilder<'a, DB>, column: &str)
+    where
+        DB: Database,
+        String: Encode<'a, DB> + Type<DB>,
+    {
+        qb.push(format!(
+            r#" AND {column} IN (
+                SELECT a."user"
+                FROM user_repos a
+                INNER JOIN repository ar ON (ar.id = a.repository)
+                WHERE a.type = 'stargazer'"#
+        ));
+
+        match &self.repo {
+            Some(repo) => qb.push(" AND ar.full_name = ").push_bind(repo.clone()),
+            None => qb.push(" AND ar.id IN (SELECT repository FROM target)"),
+        };
+        self.push_dates(qb, "a.date");
+
+        qb.push(")");
+    }
+
+    /// Append the `since` and `until` bounds on a star date column
+    fn push_dates<'a, DB>(&self, qb: &mut QueryBuilder<'a, DB>, column: &str)
+    where
+        DB: Database,
+        String: Encode<'a, DB> + Type<DB>,
+    {
+        if let Some(since) = self.since {
+            qb.push(format!(" AND {column} >= "))
+                .push_bind(timestamp(&since));
+        }
+        if let Some(until) = self.until {
+            qb.push(format!(" AND {column} < "))
+                .push_bind(timestamp(&until));
+        }
+    }
+}
+
+impl Page {
+    fn push<'a, DB>(&self, qb: &mut QueryBuilder<'a, DB>)
+    where
+        DB: Database,
+        i64: Encode<'a, DB> + Type<DB>,
+    {
+        qb.push(" LIMIT ")
+            .push_bind(i64::from(self.limit))
+            .push(" OFFSET ")
+            .push_bind(i64::from(self.offset));
+    }
+
+    /// Fetch one extra row so we know whether there is a next page
+    pub fn lookahead(self) -> Page {
+        Page {
+            limit: self.limit + 1,
+            offset: self.offset,
+        }
+    }
+
+    /// Apply the page to results that were ranked outside of SQL
+    pub fn slice<T>(&self, items: Vec<T>) -> Vec<T> {
+        items
+            .into_iter()
+            .skip(self.offset as usize)
+            .take(self.limit as usize)
+            .collect()
+    }
+}
+
+impl<T> Paginated<T> {
+    /// Page through `items`, fetched with [`Page::lookahead`]
+    pub fn new(mut items: Vec<T>, page: Page) -> Paginated<T> {
+        let more = items.len() > page.limit as usize;
+        items.truncate(page.limit as usize);
+
+        Paginated {
+            next_cursor: more
+                .then(|| page.offset.checked_add(page.limit))
+                .flatten()
+                .map(|offset| offset.to_string()),
+            items,
+            limit: page.limit,
+            offset: page.offset,
+        }
+    }
+}
+
+impl<T> Default for Paginated<T> {
+    fn default() -> Paginated<T> {
+        Paginated {
+            items: Vec::new(),
+            limit: 0,
+            offset: 0,
+            next_cursor: None,
+        }
+    }
+}
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} not found", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf};
+
+    use anyhow::Context;
+    use chrono::TimeZone;
+    use futures::TryStreamExt;
+    use sqlx::{Executor, PgPool};
+
+    use super::*;
+
+    const ALL: Page = Page {
+        limit: 100,
+        offset: 0,
+    };
+
+    fn date(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap()
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_owned())
+    }
+
+    fn words(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_owned).collect()
+    }
+
+    /// The small crawl every area starts from: three repos, of which Rocket
+    /// is a target, starred by alice and bob, who both follow carol.
+    fn crawl() -> Batch {
+        let topics = ["rust".to_owned(), "web".to_owned()];
+        let mut batch = Batch::default();
+        batch.add_repo(
+            1,
+            "rwf2/Rocket",
+            24000,
+            Some("A web framework"),
+            Some("Rust"),
+            &topics,
+        );
+        batch.add_repo(2, "tokio-rs/axum", 19000, None, Some("Rust"), &topics[..1]);
+        batch.add_repo(
+            3,
+            "vuejs/core",
+            47000,
+            Some("Vue.js"),
+            Some("TypeScript"),
+            &[],
+        );
+
+        batch.add_user(10, "alice", Some("Alice"), None);
+        batch.add_user(11, "bob", None, Some("bob@example.com"));
+        batch.add_user(12, "carol", Some("Carol"), None);
+        let place = Place {
+            country: "Germany".into(),
+            city: Some("Berlin".into()),
+        };
+        batch.set_profile(
+            10,
+            Some("Berlin"),
+            Some(&place),
+            Some("Acme Inc."),
+            None,
+            250,
+        );
+        batch.set_profile(
+            11,
+            Some("Berlin, DE"),
+            Some(&place),
+            None,
+            Some("Rustacean and cyclist"),
+            3,
+        );
+
+        for (user, day) in [(10, 1), (11, 2)] {
+            batch.add_stargazer(user, 1, &date(day));
+            batch.add_stargazer(user, 2, &date(day + 1));
+        }
+        batch.add_stargazer(10, 3, &date(5));
+        batch.add_subscriber(10, 1);
+        batch.add_activity(11, 1, "forker", Some(&date(9)));
+        batch.add_activity(11, 1, "forker", Some(&date(10)));
+        batch.add_follower(10, 12);
+        batch.add_follower(11, 12);
+        batch.add_follower(10, 11);
+        batch
+    }
+
+    async fn seeded(db: &dyn Storage) -> Result<()> {
+        db.write(&crawl()).await?;
+        db.add_target(1).await?;
+        db.add_snapshot(1, 24000).await?;
+        db.add_snapshot(1, 24100).await?;
+        Ok(())
+    }
+
+    /// Writing again, or writing a user seen elsewhere without their
+    /// profile, leaves the stored rows as they were
+    async fn rewrites(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        db.write(&crawl()).await?;
+        let mut again = Batch::default();
+        again.add_user(10, "alice", None, None);
+        again.add_stargazer(10, 3, &date(6));
+        db.write(&again).await?;
+
+        assert_eq!(db.get_users().await?.len(), 3);
+        assert_eq!(db.get_user_repo_edges().await?.len(), 7);
+        assert_eq!(db.get_user_user_edges().await?.len(), 3);
+        let alice = db.get_users().await?.into_iter().find(|u| u.id == 10);
+        assert_eq!(alice.unwrap().followers, Some(250));
+        let vue: Vec<_> = db
+            .get_user_repo_edges()
+            .await?
+            .into_iter()
+            .filter(|e| e.target == 3)
+            .map(|e| (e.source, e.date))
+            .collect();
+        assert_eq!(vue, [(10, Some("2024-01-06T12:00:00Z".to_owned()))]);
+        Ok(())
+    }
+
+    async fn sync_state(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let page = |page, etag: &str| Checkpoint {
+            page,
+            etag: etag.to_owned(),
+            items: 100,
+        };
+        db.set_checkpoints(1, "stargazers", &[page(1, "a"), page(2, "b")])
+            .await?;
+        db.set_checkpoints(1, "stargazers", &[page(2, "c")]).await?;
+        let pages = db.get_checkpoints(1, "stargazers").await?;
+        let etags: Vec<(u32, &str)> = pages.iter().map(|p| (p.page, &*p.etag)).collect();
+        assert_eq!(etags, [(1, "a"), (2, "c")]);
+        assert!(db.get_checkpoints(1, "forks").await?.is_empty());
+
+        let run = db.start_sync_run().await?;
+        db.finish_sync_run(run, "succeeded", 1200, 42, None).await?;
+        db.start_sync_run().await?;
+        db.interrupt_sync_runs().await?;
+        Ok(())
+    }
+
+    async fn aggregates(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        assert_eq!(
+            db.get_profiled_users(&[10, 11, 12, 13]).await?,
+            HashSet::from([10, 11])
+        );
+        assert_eq!(db.get_user_id("bob").await?, Some(11));
+
+        let top = db
+            .get_top_repos("stargazer", &Filter::default(), ALL)
+            .await?;
+        let top: Vec<(&str, u32)> = top.iter().map(|r| (&*r.full_name, r.count)).collect();
+        assert_eq!(
+            top,
+            [("rwf2/Rocket", 2), ("tokio-rs/axum", 2), ("vuejs/core", 1)]
+        );
+
+        let filter = Filter {
+            since: Some(date(2)),
+            ..Filter::default()
+        };
+        let top = db.get_top_repos("stargazer", &filter, ALL).await?;
+        assert_eq!(top.len(), 2);
+        assert!(top.iter().all(|r| r.count == 1));
+
+        let paged = db
+            .get_top_repos(
+                "stargazer",
+                &Filter::default(),
+                Page {
+                    limit: 1,
+                    offset: 1,
+                },
+            )
+            .await?;
+        assert_eq!(paged[0].full_name, "tokio-rs/axum");
+
+        let following = db.get_top_following(&Filter::default(), ALL).await?;
+        assert_eq!(following[0].login, "carol");
+        assert_eq!(following[0].count, 2);
+
+        let topics = db.get_top_topics(&Filter::default(), ALL).await?;
+        assert_eq!(topics[0].topic, "rust");
+        assert!((topics[0].score - 1.5).abs() < 1e-9);
+
+        let languages = db.get_top_languages(&Filter::default(), ALL).await?;
+        assert_eq!(languages[0].language, "Rust");
+
+        let countries = db.get_stargazer_countries(&Filter::default(), ALL).await?;
+        assert_eq!(countries[0].country, "Germany");
+        assert_eq!(countries[0].count, 2);
+        assert_eq!(
+            db.get_stargazer_companies(&Filter::default()).await?,
+            ["Acme Inc."]
+        );
+
+        let cohorts = db.get_cohorts(&Filter::default(), ALL).await?;
+        assert_eq!(cohorts.len(), 1);
+        assert_eq!(cohorts[0].month, "2024-01");
+        assert_eq!(cohorts[0].stargazers, 2);
+        assert_eq!(cohorts[0].watchers, 1);
+        assert_eq!(cohorts[0].forkers, 1);
+        Ok(())
+    }
+
+    async fn graph(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let stars: HashMap<i64, u32> = db.get_star_counts().await?.into_iter().collect();
+        assert_eq!(stars[&2], 2);
+        let mut costarred = db.get_costarred_for_repo(2).await?;
+        costarred.sort();
+        assert_eq!(costarred, [(3, 1)]);
+        assert_eq!(db.get_costarred_for_user(11).await?, [(2, 3, 1)]);
+
+        db.set_user_influence(&[(10, 0.25), (11, 0.5), (12, 0.25)])
+            .await?;
+        let influential = db
+            .get_influential_stargazers(&Filter::default(), ALL)
+            .await?;
+        let logins: Vec<&str> = influential.iter().map(|u| &*u.login).collect();
+        assert_eq!(logins, ["bob", "alice"]);
+
+        db.set_communities(&[(2, vec![(2, 2), (3, 1)])]).await?;
+        let communities = db.get_communities(ALL, 1).await?;
+        assert_eq!(communities[0].members, 2);
+        assert_eq!(communities[0].repos[0].full_name, "tokio-rs/axum");
+        assert_eq!(communities[0].repos.len(), 1);
+
+        assert_eq!(db.get_users().await?.len(), 3);
+        assert_eq!(db.get_repos().await?.len(), 3);
+        let names = db.get_repo_names(&[1, 2, 99]).await?;
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[&2], "tokio-rs/axum");
+        assert!(db.get_repo_names(&[]).await?.is_empty());
+        let topics = db.get_repo_topics().await?;
+        assert_eq!(topics[&1], ["rust", "web"]);
+        assert_eq!(topics[&2], ["rust"]);
+        let followers: HashMap<i64, Option<i64>> = db
+            .get_users()
+            .await?
+            .into_iter()
+            .map(|u| (u.id, u.followers))
+            .collect();
+        assert_eq!(followers[&10], Some(250));
+        assert_eq!(followers[&12], None);
+        assert_eq!(db.get_user_repo_edges().await?.len(), 7);
+        assert_eq!(db.get_user_user_edges().await?.len(), 3);
+        assert_eq!(db.get_targets().await?, [1]);
+        Ok(())
+    }
+
+    async fn details(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let user = db.get_user_detail("alice").await?.unwrap();
+        assert_eq!(user.city.as_deref(), Some("Berlin"));
+        assert_eq!(user.target_stars[0].full_name, "rwf2/Rocket");
+        assert_eq!(user.starred.len(), 2);
+        assert_eq!(user.subscribed, ["rwf2/Rocket"]);
+        assert_eq!(user.following, ["bob", "carol"]);
+        assert!(db.get_user_detail("nobody").await?.is_none());
+
+        let repo = db
+            .get_repo_detail("tokio-rs/axum", &Filter::default(), ALL)
+            .await?
+            .unwrap();
+        assert!(!repo.target);
+        assert_eq!(repo.topics, ["rust"]);
+        assert_eq!(repo.audience_count, 2);
+        assert_eq!(repo.audience.items.len(), 2);
+        let first = Page {
+            limit: 1,
+            offset: 0,
+        };
+        let repo = db
+            .get_repo_detail("tokio-rs/axum", &Filter::default(), first)
+            .await?
+            .unwrap();
+        assert_eq!(repo.audience_count, 2);
+        assert_eq!(repo.audience.items.len(), 1);
+        assert_eq!(repo.audience.next_cursor.as_deref(), Some("1"));
+        let repo = db
+            .get_repo_detail("rwf2/Rocket", &Filter::default(), ALL)
+            .await?
+            .unwrap();
+        assert!(repo.target);
+        let counts: Vec<u32> = repo.snapshots.iter().map(|s| s.stargazers).collect();
+        assert_eq!(counts, [24000, 24100]);
+        Ok(())
+    }
+
+    async fn search(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let users = db.search_users(&words("rustac"), ALL).await?;
+        assert_eq!(users[0].login, "bob");
+        let repos = db.search_repos(&words("web rock"), ALL).await?;
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].full_name, "rwf2/Rocket");
+        let repos = db.search_repos(&words("rust"), ALL).await?;
+        assert_eq!(repos.len(), 2);
+        assert!(db.search_users(&words("\"\" \""), ALL).await?.is_empty());
+        assert!(db.search_repos(&words("\"\""), ALL).await?.is_empty());
+        Ok(())
+    }
+
+    async fn company_aliases(db: &dyn Storage) -> Result<()> {
+        db.add_company_alias("acme", "Acme").await?;
+        assert_eq!(db.get_company_aliases().await?.len(), 1);
+        db.remove_company_alias("acme").await?;
+        assert!(db.get_company_aliases().await?.is_empty());
+        Ok(())
+    }
+
+    async fn exports(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let timeline: Vec<Vec<Value>> = db.export(Export::StarTimeline).try_collect().await?;
+        assert_eq!(
+            timeline,
+            [
+                vec![
+                    text("rwf2/Rocket"),
+                    text("2024-01-01"),
+                    Value::Integer(1),
+                    Value::Integer(1)
+                ],
+                vec![
+                    text("rwf2/Rocket"),
+                    text("2024-01-02"),
+                    Value::Integer(1),
+                    Value::Integer(2)
+                ],
+            ]
+        );
+        let top: Vec<Vec<Value>> = db.export(Export::TopRepos).try_collect().await?;
+        assert_eq!(top[0][0], text("rwf2/Rocket"));
+        assert_eq!(top[0][3], Value::Integer(2));
+        for export in [
+            Export::Users,
+            Export::Repos,
+            Export::Stargazers,
+            Export::Edges,
+            Export::Snapshots,
+        ] {
+            let rows: Vec<Vec<Value>> = db.export(export).try_collect().await?;
+            assert!(!rows.is_empty());
+            assert!(rows.iter().all(|row| row.len() == export.columns().len()));
+        }
+        Ok(())
+    }
+
+    /// Renames update the stored name, and a name taken over by another
+    /// account is released from its old holder
+    async fn renames(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let mut renames = Batch::default();
+        renames.add_user(11, "robert", None, None);
+        renames.add_user(13, "carol", None, None);
+        renames.add_repo(2, "tokio-rs/axum-old", 19500, None, Some("Rust"), &[]);
+        renames.add_repo(4, "tokio-rs/axum", 10, None, None, &[]);
+        db.write(&renames).await?;
+        assert_eq!(db.get_user_id("robert").await?, Some(11));
+        assert_eq!(db.get_user_id("bob").await?, None);
+        assert_eq!(db.get_user_id("carol").await?, Some(13));
+        let robert = db.get_user_detail("robert").await?.unwrap();
+        assert_eq!(robert.email.as_deref(), Some("bob@example.com"));
+        let carol = db.get_users().await?.into_iter().find(|u| u.id == 12);
+        assert_eq!(carol.unwrap().username, None);
+        assert_eq!(db.get_repo_id("tokio-rs/axum").await?, Some(4));
+        assert_eq!(db.get_repo_id("tokio-rs/axum-old").await?, Some(2));
+        let following = db.get_top_following(&Filter::default(), ALL).await?;
+        assert_eq!(following[0].login, "robert");
+
+        // Users swapping names within one batch
+        let mut swap = Batch::default();
+        swap.add_user(10, "robert", None, None);
+        swap.add_user(11, "alice", None, None);
+        db.write(&swap).await?;
+        assert_eq!(db.get_user_id("alice").await?, Some(11));
+        assert_eq!(db.get_user_id("robert").await?, Some(10));
+        Ok(())
+    }
+
+    /// Merging another crawl keeps whichever copy was seen last, and skips
+    /// rows referring to users or repos it doesn't have
+    async fn merges(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let user = |id, login: &str, seen: &str| {
+            let mut row = vec![Value::Integer(id), text(login)];
+            row.extend(vec![Value::Null; 8]);
+            row.extend([text(seen), Value::Null, Value::Null]);
+            row
+        };
+        let import = db.add_import("other.sqlite").await?;
+        let mut merge = Merge::new(import);
+        merge.add(Export::Users, user(11, "bobby", "2020-01-01T00:00:00Z"))?;
+        merge.add(Export::Users, user(14, "carol", "2099-01-01T00:00:00Z"))?;
+        merge.add(Export::Users, user(15, "alice", "2020-01-01T00:00:00Z"))?;
+        merge.add(
+            Export::Repos,
+            vec![
+                Value::Integer(5),
+                text("new/repo"),
+                Value::Integer(7),
+                Value::Null,
+                Value::Null,
+                text("2099-01-01T00:00:00Z"),
+                Value::Null,
+                Value::Null,
+            ],
+        )?;
+        for (source, target, kind, date) in [
+            (14, 5, "stargazer", text("2024-01-08T12:00:00Z")),
+            (99, 5, "stargazer", Value::Null),
+            (14, 10, "follower", Value::Null),
+        ] {
+            merge.add(
+                Export::Edges,
+                vec![
+                    Value::Integer(source),
+                    Value::Integer(target),
+                    text(kind),
+                    date,
+                ],
+            )?;
+        }
+        for repo in [5, 99] {
+            merge.add(Export::Targets, vec![Value::Integer(repo), Value::Null])?;
+            merge.add(
+                Export::Topics,
+                vec![Value::Integer(repo), Value::Null, text("cli")],
+            )?;
+            merge.add(
+                Export::Snapshots,
+                vec![
+                    Value::Integer(repo),
+                    Value::Null,
+                    Value::Integer(7),
+                    text("2024-01-08T00:00:00Z"),
+                ],
+            )?;
+        }
+        for _ in 0..2 {
+            db.merge(&merge).await?;
+        }
+        assert_eq!(db.get_user_id("bob").await?, Some(11));
+        assert_eq!(db.get_user_id("bobby").await?, None);
+        assert_eq!(db.get_user_id("carol").await?, Some(14));
+        assert_eq!(db.get_user_id("alice").await?, Some(10));
+        assert_eq!(db.get_users().await?.len(), 5);
+        assert_eq!(db.get_repo_id("new/repo").await?, Some(5));
+        assert_eq!(db.get_user_repo_edges().await?.len(), 8);
+        assert_eq!(db.get_user_user_edges().await?.len(), 4);
+
+        assert_eq!(db.get_targets().await?, [1, 5]);
+        assert_eq!(db.get_repo_topics().await?[&5], ["cli"]);
+        let repo = db
+            .get_repo_detail("new/repo", &Filter::default(), ALL)
+            .await?
+            .unwrap();
+        assert!(repo.target);
+        let counts: Vec<u32> = repo.snapshots.iter().map(|s| s.stargazers).collect();
+        assert_eq!(counts, [7]);
+        let timeline: Vec<Vec<Value>> = db.export(Export::StarTimeline).try_collect().await?;
+        assert_eq!(
+            timeline[0],
+            [
+                text("new/repo"),
+                text("2024-01-08"),
+                Value::Integer(1),
+                Value::Integer(1)
+            ]
+        );
+        Ok(())
+    }
+
+    /// Large batches are split across several inserts
+    async fn large_batches(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let mut batch = Batch::default();
+        for id in 100..2600 {
+            batch.add_user(id, &format!("user{id}"), None, None);
+            batch.add_follower(id, 12);
+        }
+        db.write(&batch).await?;
+        assert_eq!(db.get_users().await?.len(), 2503);
+        assert_eq!(db.get_user_user_edges().await?.len(), 2503);
+        Ok(())
+    }
+
+    /// A followee who later stars a target still needs their profile
+    async fn profiles(db: &dyn Storage) -> Result<()> {
+        seeded(db).await?;
+        let mut followed = Batch::default();
+        followed.add_user(20, "dave", None, None);
+        followed.add_follower(10, 20);
+        db.write(&followed).await?;
+        let mut starred = Batch::default();
+        starred.add_user(20, "dave", None, None);
+        starred.add_stargazer(20, 1, &date(7));
+        db.write(&starred).await?;
+        assert!(db.get_profiled_users(&[20]).await?.is_empty());
+        starred.set_profile(20, Some("Paris"), None, None, None, 0);
+        db.write(&starred).await?;
+        assert_eq!(db.get_profiled_users(&[20]).await?, HashSet::from([20]));
+        Ok(())
+    }
+
+    /// A SQLite file in the temp dir, removed when dropped
+    struct TempSqlite(PathBuf);
+
+    impl TempSqlite {
+        fn new(name: &str) -> Self {
+            let file = format!("stargazers-test-{}-{name}.sqlite", std::process::id());
+            Self(env::temp_dir().join(file))
+        }
+
+        fn url(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempSqlite {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{suffix}", self.0.display()));
+            }
+        }
+    }
+
+    /// Connects to the database in `STARGAZERS_TEST_POSTGRES_URL` with its
+    /// own schema per test, which is dropped first, so point it at a
+    /// throwaway database
+    async fn postgres(name: &str) -> Result<Box<dyn Storage>> {
+        let url = env::var("STARGAZERS_TEST_POSTGRES_URL")
+            .context("STARGAZERS_TEST_POSTGRES_URL is not set")?;
+        let schema = format!("test_{name}");
+        let pool = PgPool::connect(&url).await?;
+        pool.execute(&*format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema};"
+        ))
+        .await?;
+        pool.close().await;
+
+        let separator = if url.contains('?') { '&' } else { '?' };
+        connect(&format!("{url}{separator}options=-csearch_path%3D{schema}")).await
+    }
+
+    /// Runs each area against a fresh SQLite file, and against PostgreSQL
+    /// with `cargo test -- --ignored`
+    macro_rules! backends {
+        ($($area:ident),* $(,)?) => {
+            mod sqlite {
+                $(
+                    #[tokio::test]
+                    async fn $area() -> anyhow::Result<()> {
+                        let file = super::TempSqlite::new(stringify!($area));
+                        super::$area(&*super::connect(file.url()).await?).await
+                    }
+                )*
+            }
+
+            mod postgres {
+                $(
+                    #[tokio::test]
+                    #[ignore = "needs STARGAZERS_TEST_POSTGRES_URL"]
+                    async fn $area() -> anyhow::Result<()> {
+                        super::$area(&*super::postgres(stringify!($area)).await?).await
+                    }
+                )*
+            }
+        };
+    }
+
+    backends!(
+        rewrites,
+        sync_state,
+        aggregates,
+        graph,
+        details,
+        search,
+        company_aliases,
+        exports,
+        renames,
+        merges,
+        large_batches,
+        profiles,
+    );
+
+    /// The database can be read, but not written, without migrating
+    #[tokio::test]
+    async fn read_only_sqlite() -> Result<()> {
+        let file = TempSqlite::new("read_only");
+        let db = connect(file.url()).await?;
+        seeded(&*db).await?;
+        let read_only = connect_read_only(file.url()).await?;
+        assert_eq!(read_only.get_users().await?.len(), 3);
+        assert!(read_only.add_target(2).await.is_err());
+        Ok(())
+    }
+}
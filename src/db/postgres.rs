@@ -0,0 +1,963 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::{stream::BoxStream, StreamExt};
+use sqlx::{
+    migrate::Migrator, postgres::PgConnectOptions, query, query_as, query_scalar, PgPool, Postgres,
+    QueryBuilder, Row,
+};
+
+use super::{
+    check_schema, timestamp, Batch, Checkpoint, Cohort, Community, CountryCount, EdgeRecord,
+    Export, Filter, LanguageScore, Merge, Page, Paginated, RepoCount, RepoDetail, RepoHit,
+    RepoRecord, Snapshot, Storage, TopicScore, UserCount, UserDetail, UserHit, UserRecord,
+    UserScore, Value,
+};
+use crate::geo::Place;
+
+static MIGRATOR: Migrator = sqlx::migrate!("db/postgres");
+
+/// A PostgreSQL database, e.g. one shared by a team
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn open(url: &str) -> Result<PostgresStorage> {
+        let pool = PgPool::connect(url).await?;
+        MIGRATOR.run(&pool).await?;
+
+        Ok(PostgresStorage { pool })
+    }
+
+    /// Connect in read-only transactions, without migrating
+    pub async fn open_read_only(url: &str) -> Result<PostgresStorage> {
+        let options: PgConnectOptions = url.parse()?;
+        let pool = PgPool::connect_with(options.options([("default_transaction_read_only", "on")]))
+            .await?;
+
+        let version = query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&pool)
+            .await
+            .context("Not a stargazers database")?;
+        check_schema(version, &MIGRATOR)?;
+
+        Ok(PostgresStorage { pool })
+    }
+}
+
+/// Turn words into a `tsquery` that matches each one as a prefix, e.g.
+/// `rust web` becomes `rust:* & web:*`. Punctuation splits words, as it does
+/// when the search columns are indexed, and keeps query syntax out.
+fn tsquery(words: &[String]) -> String {
+    words
+        .iter()
+        .flat_map(|word| word.split(|c: char| !c.is_alphanumeric()))
+        .filter(|part| !part.is_empty())
+        .map(|part| format!("{}:*", part.to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(" & ")
+}
+
+/// Count and ID columns are BIGINT, but the API reports counts as `u32`
+fn count(row: &sqlx::postgres::PgRow, column: &str) -> u32 {
+    row.get::<i64, _>(column) as u32
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn write(&self, batch: &Batch) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for mut insert in batch.inserts::<Postgres>() {
+            insert.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn add_import(&self, source: &str) -> Result<i64> {
+        Ok(
+            query("INSERT INTO import (source, imported_at) VALUES ($1, $2) RETURNING id")
+                .bind(source)
+                .bind(timestamp(&Utc::now()))
+                .fetch_one(&self.pool)
+                .await?
+                .get(0),
+        )
+    }
+
+    async fn merge(&self, merge: &Merge) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for mut insert in merge.inserts::<Postgres>() {
+            insert.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_profiled_users(&self, ids: &[i64]) -> Result<HashSet<i64>> {
+        Ok(
+            query(r#"SELECT id FROM "user" WHERE profiled_at IS NOT NULL AND id = ANY($1)"#)
+                .bind(ids)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| row.get("id"))
+                .collect(),
+        )
+    }
+
+    async fn set_user_place(&self, id: i64, place: Option<&Place>) -> Result<()> {
+        query(r#"UPDATE "user" SET country = $1, city = $2 WHERE id = $3"#)
+            .bind(place.map(|p| &p.country))
+            .bind(place.and_then(|p| p.city.as_ref()))
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_user_id(&self, username: &str) -> Result<Option<i64>> {
+        Ok(query(r#"SELECT id FROM "user" WHERE username = $1"#)
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.get("id")))
+    }
+
+    async fn get_user_locations(&self) -> Result<Vec<(i64, String)>> {
+        Ok(
+            query(r#"SELECT id, location FROM "user" WHERE location IS NOT NULL"#)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.get("id"), row.get("location")))
+                .collect(),
+        )
+    }
+
+    async fn get_users(&self) -> Result<Vec<UserRecord>> {
+        Ok(query_as(
+            r#"SELECT id, username, name, email, location, country, company, bio, followers
+            FROM "user""#,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn has_organization(&self, login: &str) -> Result<bool> {
+        Ok(query("SELECT login FROM organization WHERE login = $1")
+            .bind(login)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some())
+    }
+
+    async fn add_organization(&self, login: &str, name: Option<&str>) -> Result<()> {
+        query(
+            "INSERT INTO organization (login, name) VALUES ($1, $2)
+            ON CONFLICT (login) DO UPDATE SET name = excluded.name",
+        )
+        .bind(login)
+        .bind(name)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_organizations(&self) -> Result<Vec<(String, String)>> {
+        Ok(
+            query("SELECT login, name FROM organization WHERE name IS NOT NULL")
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.get("login"), row.get("name")))
+                .collect(),
+        )
+    }
+
+    async fn add_company_alias(&self, alias: &str, company: &str) -> Result<()> {
+        query(
+            "INSERT INTO company_alias (alias, company) VALUES ($1, $2)
+            ON CONFLICT (alias) DO UPDATE SET company = excluded.company",
+        )
+        .bind(alias)
+        .bind(company)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_company_alias(&self, alias: &str) -> Result<()> {
+        query("DELETE FROM company_alias WHERE alias = $1")
+            .bind(alias)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_company_aliases(&self) -> Result<Vec<(String, String)>> {
+        Ok(
+            query("SELECT alias, company FROM company_alias ORDER BY alias")
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.get("alias"), row.get("company")))
+                .collect(),
+        )
+    }
+
+    async fn add_target(&self, repo: i64) -> Result<()> {
+        query("INSERT INTO target (repository) VALUES ($1) ON CONFLICT DO NOTHING")
+            .bind(repo)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_targets(&self) -> Result<Vec<i64>> {
+        Ok(query("SELECT repository FROM target")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| row.get("repository"))
+            .collect())
+    }
+
+    async fn get_repo_id(&self, full_name: &str) -> Result<Option<i64>> {
+        Ok(query("SELECT id FROM repository WHERE full_name = $1")
+            .bind(full_name)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.get("id")))
+    }
+
+    async fn get_repo_names(&self, ids: &[i64]) -> Result<HashMap<i64, String>> {
+        Ok(query(
+            "SELECT id, full_name FROM repository WHERE id = ANY($1) AND full_name IS NOT NULL",
+        )
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("id"), row.get("full_name")))
+        .collect())
+    }
+
+    async fn get_repos(&self) -> Result<Vec<RepoRecord>> {
+        Ok(
+            query_as("SELECT id, full_name, stargazers, description, language FROM repository")
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn get_repo_topics(&self) -> Result<HashMap<i64, Vec<String>>> {
+        let rows: Vec<(i64, String)> =
+            query_as("SELECT repository, topic FROM repository_topic ORDER BY repository, topic")
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut topics: HashMap<i64, Vec<String>> = HashMap::new();
+        for (repo, topic) in rows {
+            topics.entry(repo).or_default().push(topic);
+        }
+        Ok(topics)
+    }
+
+    async fn get_user_repo_edges(&self) -> Result<Vec<EdgeRecord>> {
+        Ok(query_as(
+            r#"SELECT "user" AS source, repository AS target, type, date
+            FROM user_repos"#,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn get_user_user_edges(&self) -> Result<Vec<EdgeRecord>> {
+        Ok(query_as(
+            "SELECT subject AS source, linked AS target, type, NULL::TEXT AS date
+            FROM user_users",
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    fn export(&self, export: Export) -> BoxStream<'_, Result<Vec<Value>>> {
+        query(export.sql())
+            .fetch(&self.pool)
+            .map(move |row| export.decode(&row?))
+            .boxed()
+    }
+
+    async fn add_snapshot(&self, repo: i64, stargazers: i64) -> Result<()> {
+        query(
+            "INSERT INTO repository_snapshot (repository, stargazers, taken_at)
+            VALUES ($1, $2, $3)",
+        )
+        .bind(repo)
+        .bind(stargazers)
+        .bind(timestamp(&Utc::now()))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_snapshots(&self, repo: i64) -> Result<Vec<Snapshot>> {
+        Ok(query_as(
+            "SELECT stargazers, taken_at
+            FROM repository_snapshot
+            WHERE repository = $1
+            ORDER BY taken_at",
+        )
+        .bind(repo)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn get_checkpoints(&self, repo: i64, list: &str) -> Result<Vec<Checkpoint>> {
+        Ok(query_as(
+            "SELECT page, etag, items FROM sync_checkpoint
+            WHERE repository = $1 AND list = $2
+            ORDER BY page",
+        )
+        .bind(repo)
+        .bind(list)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn set_checkpoints(&self, repo: i64, list: &str, pages: &[Checkpoint]) -> Result<()> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+
+        let now = timestamp(&Utc::now());
+        let mut qb = QueryBuilder::<Postgres>::new(
+            "INSERT INTO sync_checkpoint (repository, list, page, etag, items, updated_at) ",
+        );
+        qb.push_values(pages, |mut row, page| {
+            row.push_bind(repo)
+                .push_bind(list)
+                .push_bind(i64::from(page.page))
+                .push_bind(&page.etag)
+                .push_bind(i64::from(page.items))
+                .push_bind(&now);
+        });
+        qb.push(
+            " ON CONFLICT (repository, list, page) DO UPDATE SET
+            etag = excluded.etag, items = excluded.items, updated_at = excluded.updated_at",
+        );
+        qb.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn start_sync_run(&self) -> Result<i64> {
+        Ok(
+            query("INSERT INTO sync_run (started_at, status) VALUES ($1, 'running') RETURNING id")
+                .bind(timestamp(&Utc::now()))
+                .fetch_one(&self.pool)
+                .await?
+                .get(0),
+        )
+    }
+
+    async fn finish_sync_run(
+        &self,
+        id: i64,
+        status: &str,
+        duration_ms: i64,
+        requests: i64,
+        error: Option<&str>,
+    ) -> Result<()> {
+        query(
+            "UPDATE sync_run
+            SET finished_at = $1, status = $2, duration_ms = $3, requests = $4, error = $5
+            WHERE id = $6",
+        )
+        .bind(timestamp(&Utc::now()))
+        .bind(status)
+        .bind(duration_ms)
+        .bind(requests)
+        .bind(error)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn interrupt_sync_runs(&self) -> Result<()> {
+        query("UPDATE sync_run SET status = 'interrupted' WHERE status = 'running'")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_user_influence(&self, scores: &[(i64, f64)]) -> Result<()> {
+        let computed_at = timestamp(&Utc::now());
+        let mut tx = self.pool.begin().await?;
+
+        query("DELETE FROM user_influence")
+            .execute(&mut *tx)
+            .await?;
+        for (user, score) in scores {
+            query(r#"INSERT INTO user_influence ("user", score, computed_at) VALUES ($1, $2, $3)"#)
+                .bind(user)
+                .bind(score)
+                .bind(&computed_at)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_communities(&self, communities: &[(u32, Vec<(i64, u32)>)]) -> Result<()> {
+        let computed_at = timestamp(&Utc::now());
+        let mut tx = self.pool.begin().await?;
+
+        query("DELETE FROM community_repos")
+            .execute(&mut *tx)
+            .await?;
+        query("DELETE FROM community").execute(&mut *tx).await?;
+        for (id, (members, repos)) in communities.iter().enumerate() {
+            let id = id as i64 + 1;
+            query("INSERT INTO community (id, members, computed_at) VALUES ($1, $2, $3)")
+                .bind(id)
+                .bind(i64::from(*members))
+                .bind(&computed_at)
+                .execute(&mut *tx)
+                .await?;
+
+            for (repo, stargazers) in repos {
+                query(
+                    "INSERT INTO community_repos (community, repository, stargazers)
+                    VALUES ($1, $2, $3)",
+                )
+                .bind(id)
+                .bind(repo)
+                .bind(i64::from(*stargazers))
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_star_counts(&self) -> Result<Vec<(i64, u32)>> {
+        Ok(query(
+            "SELECT repository, count(*) AS count
+            FROM user_repos
+            WHERE type = 'stargazer'
+            GROUP BY repository",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("repository"), count(&row, "count")))
+        .collect())
+    }
+
+    async fn get_costarred_for_user(&self, user: i64) -> Result<Vec<(i64, i64, u32)>> {
+        Ok(query(
+            r#"WITH mine AS (
+                SELECT repository
+                FROM user_repos
+                WHERE "user" = $1 AND type = 'stargazer'
+            )
+            SELECT a.repository AS source, b.repository AS candidate, count(*) AS count
+            FROM user_repos a
+            INNER JOIN user_repos b ON (a."user" = b."user" AND b.type = 'stargazer')
+            WHERE a.type = 'stargazer'
+            AND a.repository IN (SELECT repository FROM mine)
+            AND a.repository NOT IN (SELECT repository FROM target)
+            AND b.repository NOT IN (SELECT repository FROM mine)
+            AND b.repository NOT IN (SELECT repository FROM target)
+            GROUP BY a.repository, b.repository"#,
+        )
+        .bind(user)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| {
+            (
+                row.get("source"),
+                row.get("candidate"),
+                count(&row, "count"),
+            )
+        })
+        .collect())
+    }
+
+    async fn get_costarred_for_repo(&self, repo: i64) -> Result<Vec<(i64, u32)>> {
+        Ok(query(
+            r#"SELECT b.repository, count(*) AS count
+            FROM user_repos a
+            INNER JOIN user_repos b ON (a."user" = b."user" AND b.type = 'stargazer')
+            WHERE a.repository = $1 AND a.type = 'stargazer'
+            AND b.repository != a.repository
+            AND b.repository NOT IN (SELECT repository FROM target)
+            GROUP BY b.repository"#,
+        )
+        .bind(repo)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("repository"), count(&row, "count")))
+        .collect())
+    }
+
+    async fn get_top_repos(
+        &self,
+        kind: &str,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<RepoCount>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            "SELECT full_name, count(*) AS count
+            FROM repository r
+            INNER JOIN user_repos ur ON (r.id = ur.repository)
+            WHERE r.full_name IS NOT NULL
+            AND ur.type = ",
+        );
+        qb.push_bind(kind.to_owned());
+        if !filter.is_empty() {
+            filter.push_audience(&mut qb, r#"ur."user""#);
+        }
+        qb.push(
+            " GROUP BY r.id
+            ORDER BY count DESC, full_name",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_top_following(&self, filter: &Filter, page: Page) -> Result<Vec<UserCount>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"SELECT username AS login, count(*) AS count
+            FROM "user" u
+            INNER JOIN user_users uu ON (u.id = uu.linked AND uu.type = 'follower')
+            WHERE u.username IS NOT NULL"#,
+        );
+        if !filter.is_empty() {
+            filter.push_audience(&mut qb, "uu.subject");
+        }
+        qb.push(
+            " GROUP BY u.id
+            ORDER BY count DESC, login",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_influential_stargazers(
+        &self,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<UserScore>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"SELECT username AS login, score
+            FROM "user" u
+            INNER JOIN user_influence ui ON (u.id = ui."user")
+            WHERE u.username IS NOT NULL"#,
+        );
+        filter.push_audience(&mut qb, "u.id");
+        qb.push(" ORDER BY score DESC, login");
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_communities(&self, page: Page, repos: u32) -> Result<Vec<Community>> {
+        let mut communities: Vec<Community> = query(
+            "SELECT id, members
+            FROM community
+            ORDER BY id
+            LIMIT $1 OFFSET $2",
+        )
+        .bind(i64::from(page.limit))
+        .bind(i64::from(page.offset))
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| Community {
+            id: row.get("id"),
+            members: count(&row, "members"),
+            repos: Vec::new(),
+        })
+        .collect();
+
+        for c in communities.iter_mut() {
+            c.repos = query_as(
+                "SELECT full_name, cr.stargazers AS count
+                FROM repository r
+                INNER JOIN community_repos cr ON (r.id = cr.repository)
+                WHERE r.full_name IS NOT NULL
+                AND cr.community = $1
+                ORDER BY count DESC, full_name
+                LIMIT $2",
+            )
+            .bind(c.id)
+            .bind(i64::from(repos))
+            .fetch_all(&self.pool)
+            .await?;
+        }
+
+        Ok(communities)
+    }
+
+    async fn get_top_topics(&self, filter: &Filter, page: Page) -> Result<Vec<TopicScore>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"WITH starred AS (
+                SELECT "user", repository
+                FROM user_repos
+                WHERE type = 'stargazer'
+                AND repository NOT IN (SELECT repository FROM target)"#,
+        );
+        if !filter.is_empty() {
+            filter.push_audience(&mut qb, r#""user""#);
+        }
+        qb.push(
+            r#"),
+            weight AS (
+                SELECT "user", 1.0 / count(*) AS weight
+                FROM starred
+                GROUP BY "user"
+            )
+            SELECT topic, sum(w.weight)::DOUBLE PRECISION AS score
+            FROM starred s
+            INNER JOIN weight w ON (w."user" = s."user")
+            INNER JOIN repository_topic rt ON (rt.repository = s.repository)
+            GROUP BY topic
+            ORDER BY score DESC, topic"#,
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_top_languages(&self, filter: &Filter, page: Page) -> Result<Vec<LanguageScore>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"WITH starred AS (
+                SELECT "user", repository
+                FROM user_repos
+                WHERE type = 'stargazer'
+                AND repository NOT IN (SELECT repository FROM target)"#,
+        );
+        if !filter.is_empty() {
+            filter.push_audience(&mut qb, r#""user""#);
+        }
+        qb.push(
+            r#"),
+            weight AS (
+                SELECT "user", 1.0 / count(*) AS weight
+                FROM starred
+                GROUP BY "user"
+            )
+            SELECT language, sum(w.weight)::DOUBLE PRECISION AS score
+            FROM starred s
+            INNER JOIN weight w ON (w."user" = s."user")
+            INNER JOIN repository r ON (r.id = s.repository)
+            WHERE language IS NOT NULL
+            GROUP BY language
+            ORDER BY score DESC, language"#,
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_stargazer_countries(
+        &self,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<CountryCount>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"SELECT country, count(*) AS count
+            FROM "user" u
+            WHERE country IS NOT NULL"#,
+        );
+        filter.push_audience(&mut qb, "u.id");
+        qb.push(
+            " GROUP BY country
+            ORDER BY count DESC, country",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_stargazer_companies(&self, filter: &Filter) -> Result<Vec<String>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"SELECT company
+            FROM "user" u
+            WHERE company IS NOT NULL"#,
+        );
+        filter.push_audience(&mut qb, "u.id");
+
+        Ok(qb
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| row.get("company"))
+            .collect())
+    }
+
+    async fn get_cohorts(&self, filter: &Filter, page: Page) -> Result<Vec<Cohort>> {
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"SELECT substr(s.date, 1, 7) AS month,
+                count(*) AS stargazers,
+                count(w."user") AS watchers,
+                count(f."user") AS forkers,
+                count(i."user") AS issue_authors,
+                count(c."user") AS contributors
+            FROM user_repos s
+            INNER JOIN repository r ON (r.id = s.repository)
+            LEFT JOIN user_repos w ON (
+                w."user" = s."user" AND w.repository = s.repository AND w.type = 'subscriber'
+            )
+            LEFT JOIN user_repos f ON (
+                f."user" = s."user" AND f.repository = s.repository AND f.type = 'forker'
+                AND f.date >= s.date
+            )
+            LEFT JOIN user_repos i ON (
+                i."user" = s."user" AND i.repository = s.repository AND i.type = 'issue_author'
+                AND i.date >= s.date
+            )
+            LEFT JOIN user_repos c ON (
+                c."user" = s."user" AND c.repository = s.repository AND c.type = 'contributor'
+            )
+            WHERE s.type = 'stargazer'
+            AND s.date IS NOT NULL"#,
+        );
+        match &filter.repo {
+            Some(repo) => qb.push(" AND r.full_name = ").push_bind(repo.clone()),
+            None => qb.push(" AND r.id IN (SELECT repository FROM target)"),
+        };
+        filter.push_dates(&mut qb, "s.date");
+        qb.push(
+            " GROUP BY month
+            ORDER BY month",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_user_detail(&self, username: &str) -> Result<Option<UserDetail>> {
+        let Some(mut user): Option<UserDetail> = query_as(
+            r#"SELECT id, username AS login, name, email, location, country, city, company, bio
+            FROM "user"
+            WHERE username = $1"#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        user.target_stars = query_as(
+            r#"SELECT full_name, date
+            FROM user_repos ur
+            INNER JOIN repository r ON (r.id = ur.repository)
+            WHERE r.full_name IS NOT NULL
+            AND ur."user" = $1
+            AND ur.type = 'stargazer'
+            AND r.id IN (SELECT repository FROM target)
+            ORDER BY full_name"#,
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        user.starred = query_as(
+            r#"SELECT full_name, date
+            FROM user_repos ur
+            INNER JOIN repository r ON (r.id = ur.repository)
+            WHERE r.full_name IS NOT NULL
+            AND ur."user" = $1
+            AND ur.type = 'stargazer'
+            AND r.id NOT IN (SELECT repository FROM target)
+            ORDER BY full_name"#,
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        user.subscribed = query(
+            r#"SELECT full_name
+            FROM user_repos ur
+            INNER JOIN repository r ON (r.id = ur.repository)
+            WHERE r.full_name IS NOT NULL
+            AND ur."user" = $1
+            AND ur.type = 'subscriber'
+            ORDER BY full_name"#,
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("full_name"))
+        .collect();
+
+        user.following = query(
+            r#"SELECT username
+            FROM user_users uu
+            INNER JOIN "user" u ON (u.id = uu.linked)
+            WHERE u.username IS NOT NULL
+            AND uu.subject = $1
+            AND uu.type = 'follower'
+            ORDER BY username"#,
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("username"))
+        .collect();
+
+        user.followers = query(
+            r#"SELECT username
+            FROM user_users uu
+            INNER JOIN "user" u ON (u.id = uu.subject)
+            WHERE u.username IS NOT NULL
+            AND uu.linked = $1
+            AND uu.type = 'follower'
+            ORDER BY username"#,
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("username"))
+        .collect();
+
+        Ok(Some(user))
+    }
+
+    async fn get_repo_detail(
+        &self,
+        full_name: &str,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Option<RepoDetail>> {
+        let Some(mut repo): Option<RepoDetail> = query_as(
+            "SELECT id, full_name, stargazers, description, language,
+                id IN (SELECT repository FROM target) AS target
+            FROM repository
+            WHERE full_name = $1",
+        )
+        .bind(full_name)
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        repo.topics = query(
+            "SELECT topic
+            FROM repository_topic
+            WHERE repository = $1
+            ORDER BY topic",
+        )
+        .bind(repo.id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("topic"))
+        .collect();
+
+        repo.snapshots = self.get_snapshots(repo.id).await?;
+
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"SELECT username AS login, date
+            FROM user_repos ur
+            INNER JOIN "user" u ON (u.id = ur."user")
+            WHERE u.username IS NOT NULL
+            AND ur.type = 'stargazer'
+            AND ur.repository = "#,
+        );
+        qb.push_bind(repo.id);
+        filter.push_audience(&mut qb, r#"ur."user""#);
+        qb.push(" ORDER BY date, login");
+        page.lookahead().push(&mut qb);
+        repo.audience = Paginated::new(qb.build_query_as().fetch_all(&self.pool).await?, page);
+
+        let mut qb = QueryBuilder::<Postgres>::new(
+            r#"SELECT COUNT(*) AS count
+            FROM user_repos ur
+            INNER JOIN "user" u ON (u.id = ur."user")
+            WHERE u.username IS NOT NULL
+            AND ur.type = 'stargazer'
+            AND ur.repository = "#,
+        );
+        qb.push_bind(repo.id);
+        filter.push_audience(&mut qb, r#"ur."user""#);
+        repo.audience_count = qb
+            .build()
+            .fetch_one(&self.pool)
+            .await?
+            .get::<i64, _>("count") as u32;
+
+        Ok(Some(repo))
+    }
+
+    async fn search_users(&self, words: &[String], page: Page) -> Result<Vec<UserHit>> {
+        let tsquery = tsquery(words);
+        if tsquery.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(query_as(
+            r#"SELECT username AS login, name, company
+            FROM "user", to_tsquery('simple', $1) q
+            WHERE username IS NOT NULL
+            AND search @@ q
+            ORDER BY ts_rank(search, q) DESC, login
+            LIMIT $2 OFFSET $3"#,
+        )
+        .bind(tsquery)
+        .bind(i64::from(page.limit))
+        .bind(i64::from(page.offset))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn search_repos(&self, words: &[String], page: Page) -> Result<Vec<RepoHit>> {
+        let tsquery = tsquery(words);
+        if tsquery.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(query_as(
+            r#"SELECT r.full_name, r.description
+            FROM repository r
+            CROSS JOIN LATERAL (
+                SELECT r.search || setweight(to_tsvector('simple', regexp_replace(
+                    coalesce(string_agg(topic, ' '), ''), '[^[:alnum:]]+', ' ', 'g'
+                )), 'B') AS search
+                FROM repository_topic
+                WHERE repository = r.id
+            ) t,
+            to_tsquery('simple', $1) q
+            WHERE r.full_name IS NOT NULL
+            AND t.search @@ q
+            ORDER BY ts_rank(t.search, q) DESC, r.full_name
+            LIMIT $2 OFFSET $3"#,
+        )
+        .bind(tsquery)
+        .bind(i64::from(page.limit))
+        .bind(i64::from(page.offset))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
@@ -0,0 +1,979 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::{stream::BoxStream, StreamExt};
+use sqlx::{
+    migrate::Migrator,
+    query, query_as, query_scalar,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
+    Pool, QueryBuilder, Row, Sqlite,
+};
+
+use super::{
+    check_schema, timestamp, Batch, Checkpoint, Cohort, Community, CountryCount, EdgeRecord,
+    Export, Filter, LanguageScore, Merge, Page, Paginated, RepoCount, RepoDetail, RepoHit,
+    RepoRecord, Snapshot, Storage, TopicScore, UserCount, UserDetail, UserHit, UserRecord,
+    UserScore, Value,
+};
+use crate::geo::Place;
+
+static MIGRATOR: Migrator = sqlx::migrate!("db/migrations");
+
+/// A local SQLite database file
+pub struct SqliteStorage {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteStorage {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStorage> {
+        if let Some(dir) = path.as_ref().parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // WAL lets the API read while a download is writing, and only syncs
+        // at checkpoints rather than on every commit
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal);
+        let pool = Pool::<Sqlite>::connect_with(options).await?;
+        MIGRATOR.run(&pool).await?;
+
+        Ok(SqliteStorage { pool })
+    }
+
+    /// Open an existing file read-only, without migrating it
+    pub async fn open_read_only<P: AsRef<Path>>(path: P) -> Result<SqliteStorage> {
+        let path = path.as_ref();
+        let options = SqliteConnectOptions::new().filename(path).read_only(true);
+        let pool = Pool::<Sqlite>::connect_with(options)
+            .await
+            .with_context(|| format!("Could not open {}", path.display()))?;
+
+        let version = query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&pool)
+            .await
+            .with_context(|| format!("{} is not a stargazers database", path.display()))?;
+        check_schema(version, &MIGRATOR)?;
+
+        Ok(SqliteStorage { pool })
+    }
+}
+
+/// Turn words into an FTS5 query that matches each one as a prefix, e.g.
+/// `rust web` becomes `"rust"* "web"*`. Quoting each word stops FTS5 syntax
+/// such as `AND` or `-` in user input from being interpreted.
+fn fts5_pattern(words: &[String]) -> String {
+    words
+        .iter()
+        .map(|word| word.replace('"', ""))
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{word}\"*"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn write(&self, batch: &Batch) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for mut insert in batch.inserts::<Sqlite>() {
+            insert.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn add_import(&self, source: &str) -> Result<i64> {
+        Ok(
+            query("INSERT INTO import (source, imported_at) VALUES (?, ?) RETURNING id")
+                .bind(source)
+                .bind(timestamp(&Utc::now()))
+                .fetch_one(&self.pool)
+                .await?
+                .get(0),
+        )
+    }
+
+    async fn merge(&self, merge: &Merge) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for mut insert in merge.inserts::<Sqlite>() {
+            insert.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_profiled_users(&self, ids: &[i64]) -> Result<HashSet<i64>> {
+        if ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT id FROM user WHERE profiled_at IS NOT NULL AND id IN (",
+        );
+        let mut separated = qb.separated(", ");
+        for id in ids {
+            separated.push_bind(id);
+        }
+        qb.push(")");
+
+        Ok(qb
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| row.get("id"))
+            .collect())
+    }
+
+    async fn set_user_place(&self, id: i64, place: Option<&Place>) -> Result<()> {
+        query("UPDATE user SET country = ?, city = ? WHERE id = ?")
+            .bind(place.map(|p| &p.country))
+            .bind(place.and_then(|p| p.city.as_ref()))
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_user_id(&self, username: &str) -> Result<Option<i64>> {
+        Ok(query("SELECT id FROM user WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.get("id")))
+    }
+
+    async fn get_user_locations(&self) -> Result<Vec<(i64, String)>> {
+        Ok(
+            query("SELECT id, location FROM user WHERE location IS NOT NULL")
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.get("id"), row.get("location")))
+                .collect(),
+        )
+    }
+
+    async fn get_users(&self) -> Result<Vec<UserRecord>> {
+        Ok(query_as(
+            "SELECT id, username, name, email, location, country, company, bio, followers
+            FROM user",
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn has_organization(&self, login: &str) -> Result<bool> {
+        Ok(query("SELECT login FROM organization WHERE login = ?")
+            .bind(login)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some())
+    }
+
+    async fn add_organization(&self, login: &str, name: Option<&str>) -> Result<()> {
+        query("INSERT OR REPLACE INTO organization (login, name) VALUES (?, ?)")
+            .bind(login)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_organizations(&self) -> Result<Vec<(String, String)>> {
+        Ok(
+            query("SELECT login, name FROM organization WHERE name IS NOT NULL")
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.get("login"), row.get("name")))
+                .collect(),
+        )
+    }
+
+    async fn add_company_alias(&self, alias: &str, company: &str) -> Result<()> {
+        query("INSERT OR REPLACE INTO company_alias (alias, company) VALUES (?, ?)")
+            .bind(alias)
+            .bind(company)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_company_alias(&self, alias: &str) -> Result<()> {
+        query("DELETE FROM company_alias WHERE alias = ?")
+            .bind(alias)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_company_aliases(&self) -> Result<Vec<(String, String)>> {
+        Ok(
+            query("SELECT alias, company FROM company_alias ORDER BY alias")
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.get("alias"), row.get("company")))
+                .collect(),
+        )
+    }
+
+    async fn add_target(&self, repo: i64) -> Result<()> {
+        query("INSERT OR IGNORE INTO target (repository) VALUES (?)")
+            .bind(repo)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_targets(&self) -> Result<Vec<i64>> {
+        Ok(query("SELECT repository FROM target")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| row.get("repository"))
+            .collect())
+    }
+
+    async fn get_repo_id(&self, full_name: &str) -> Result<Option<i64>> {
+        Ok(query("SELECT id FROM repository WHERE full_name = ?")
+            .bind(full_name)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.get("id")))
+    }
+
+    async fn get_repo_names(&self, ids: &[i64]) -> Result<HashMap<i64, String>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "SELECT id, full_name FROM repository WHERE full_name IS NOT NULL AND id IN (",
+        );
+        let mut separated = qb.separated(", ");
+        for id in ids {
+            separated.push_bind(id);
+        }
+        qb.push(")");
+
+        Ok(qb
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| (row.get("id"), row.get("full_name")))
+            .collect())
+    }
+
+    async fn get_repos(&self) -> Result<Vec<RepoRecord>> {
+        Ok(
+            query_as("SELECT id, full_name, stargazers, description, language FROM repository")
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn get_repo_topics(&self) -> Result<HashMap<i64, Vec<String>>> {
+        let rows: Vec<(i64, String)> =
+            query_as("SELECT repository, topic FROM repository_topic ORDER BY repository, topic")
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut topics: HashMap<i64, Vec<String>> = HashMap::new();
+        for (repo, topic) in rows {
+            topics.entry(repo).or_default().push(topic);
+        }
+        Ok(topics)
+    }
+
+    async fn get_user_repo_edges(&self) -> Result<Vec<EdgeRecord>> {
+        Ok(query_as(
+            "SELECT user AS source, repository AS target, type, date
+            FROM user_repos",
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn get_user_user_edges(&self) -> Result<Vec<EdgeRecord>> {
+        Ok(query_as(
+            "SELECT subject AS source, linked AS target, type, NULL AS date
+            FROM user_users",
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    fn export(&self, export: Export) -> BoxStream<'_, Result<Vec<Value>>> {
+        query(export.sql())
+            .fetch(&self.pool)
+            .map(move |row| export.decode(&row?))
+            .boxed()
+    }
+
+    async fn add_snapshot(&self, repo: i64, stargazers: i64) -> Result<()> {
+        query(
+            "INSERT INTO repository_snapshot (repository, stargazers, taken_at) VALUES (?, ?, ?)",
+        )
+        .bind(repo)
+        .bind(stargazers)
+        .bind(timestamp(&Utc::now()))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_snapshots(&self, repo: i64) -> Result<Vec<Snapshot>> {
+        Ok(query_as(
+            "SELECT stargazers, taken_at
+            FROM repository_snapshot
+            WHERE repository = ?
+            ORDER BY taken_at",
+        )
+        .bind(repo)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn get_checkpoints(&self, repo: i64, list: &str) -> Result<Vec<Checkpoint>> {
+        Ok(query_as(
+            "SELECT page, etag, items FROM sync_checkpoint
+            WHERE repository = ? AND list = ?
+            ORDER BY page",
+        )
+        .bind(repo)
+        .bind(list)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn set_checkpoints(&self, repo: i64, list: &str, pages: &[Checkpoint]) -> Result<()> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+
+        let now = timestamp(&Utc::now());
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            "INSERT INTO sync_checkpoint (repository, list, page, etag, items, updated_at) ",
+        );
+        qb.push_values(pages, |mut row, page| {
+            row.push_bind(repo)
+                .push_bind(list)
+                .push_bind(i64::from(page.page))
+                .push_bind(&page.etag)
+                .push_bind(i64::from(page.items))
+                .push_bind(&now);
+        });
+        qb.push(
+            " ON CONFLICT (repository, list, page) DO UPDATE SET
+            etag = excluded.etag, items = excluded.items, updated_at = excluded.updated_at",
+        );
+        qb.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn start_sync_run(&self) -> Result<i64> {
+        Ok(
+            query("INSERT INTO sync_run (started_at, status) VALUES (?, 'running') RETURNING id")
+                .bind(timestamp(&Utc::now()))
+                .fetch_one(&self.pool)
+                .await?
+                .get(0),
+        )
+    }
+
+    async fn finish_sync_run(
+        &self,
+        id: i64,
+        status: &str,
+        duration_ms: i64,
+        requests: i64,
+        error: Option<&str>,
+    ) -> Result<()> {
+        query(
+            "UPDATE sync_run
+            SET finished_at = ?, status = ?, duration_ms = ?, requests = ?, error = ?
+            WHERE id = ?",
+        )
+        .bind(timestamp(&Utc::now()))
+        .bind(status)
+        .bind(duration_ms)
+        .bind(requests)
+        .bind(error)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn interrupt_sync_runs(&self) -> Result<()> {
+        query("UPDATE sync_run SET status = 'interrupted' WHERE status = 'running'")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_user_influence(&self, scores: &[(i64, f64)]) -> Result<()> {
+        let computed_at = timestamp(&Utc::now());
+        let mut tx = self.pool.begin().await?;
+
+        query("DELETE FROM user_influence")
+            .execute(&mut *tx)
+            .await?;
+        for (user, score) in scores {
+            query("INSERT INTO user_influence (user, score, computed_at) VALUES (?, ?, ?)")
+                .bind(user)
+                .bind(score)
+                .bind(&computed_at)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_communities(&self, communities: &[(u32, Vec<(i64, u32)>)]) -> Result<()> {
+        let computed_at = timestamp(&Utc::now());
+        let mut tx = self.pool.begin().await?;
+
+        query("DELETE FROM community_repos")
+            .execute(&mut *tx)
+            .await?;
+        query("DELETE FROM community").execute(&mut *tx).await?;
+        for (id, (members, repos)) in communities.iter().enumerate() {
+            let id = id as i64 + 1;
+            query("INSERT INTO community (id, members, computed_at) VALUES (?, ?, ?)")
+                .bind(id)
+                .bind(members)
+                .bind(&computed_at)
+                .execute(&mut *tx)
+                .await?;
+
+            for (repo, stargazers) in repos {
+                query(
+                    "INSERT INTO community_repos (community, repository, stargazers) VALUES (?, ?, ?)",
+                )
+                .bind(id)
+                .bind(repo)
+                .bind(stargazers)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_star_counts(&self) -> Result<Vec<(i64, u32)>> {
+        Ok(query(
+            "SELECT repository, count(*) as count
+            FROM user_repos
+            WHERE type = 'stargazer'
+            GROUP BY repository",
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("repository"), row.get("count")))
+        .collect())
+    }
+
+    async fn get_costarred_for_user(&self, user: i64) -> Result<Vec<(i64, i64, u32)>> {
+        Ok(query(
+            "WITH mine AS (
+                SELECT repository
+                FROM user_repos
+                WHERE user = ? AND type = 'stargazer'
+            )
+            SELECT a.repository AS source, b.repository AS candidate, count(*) as count
+            FROM user_repos a
+            INNER JOIN user_repos b ON (a.user = b.user AND b.type = 'stargazer')
+            WHERE a.type = 'stargazer'
+            AND a.repository IN mine
+            AND a.repository NOT IN (SELECT repository FROM target)
+            AND b.repository NOT IN mine
+            AND b.repository NOT IN (SELECT repository FROM target)
+            GROUP BY a.repository, b.repository",
+        )
+        .bind(user)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("source"), row.get("candidate"), row.get("count")))
+        .collect())
+    }
+
+    async fn get_costarred_for_repo(&self, repo: i64) -> Result<Vec<(i64, u32)>> {
+        Ok(query(
+            "SELECT b.repository, count(*) as count
+            FROM user_repos a
+            INNER JOIN user_repos b ON (a.user = b.user AND b.type = 'stargazer')
+            WHERE a.repository = ? AND a.type = 'stargazer'
+            AND b.repository != a.repository
+            AND b.repository NOT IN (SELECT repository FROM target)
+            GROUP BY b.repository",
+        )
+        .bind(repo)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("repository"), row.get("count")))
+        .collect())
+    }
+
+    async fn get_top_repos(
+        &self,
+        kind: &str,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<RepoCount>> {
+        let mut qb = QueryBuilder::new(
+            "SELECT full_name, count(*) as count
+            FROM repository r
+            INNER JOIN user_repos ur ON (r.id = ur.repository)
+            WHERE r.full_name IS NOT NULL
+            AND ur.type = ",
+        );
+        qb.push_bind(kind.to_owned());
+        if !filter.is_empty() {
+            filter.push_audience(&mut qb, "ur.user");
+        }
+        qb.push(
+            " GROUP BY ur.repository
+            ORDER BY count DESC, full_name",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_top_following(&self, filter: &Filter, page: Page) -> Result<Vec<UserCount>> {
+        let mut qb = QueryBuilder::new(
+            "SELECT username AS login, count(*) as count
+            FROM user u
+            INNER JOIN user_users uu ON (u.id = uu.linked AND uu.type = 'follower')
+            WHERE u.username IS NOT NULL",
+        );
+        if !filter.is_empty() {
+            filter.push_audience(&mut qb, "uu.subject");
+        }
+        qb.push(
+            " GROUP BY uu.linked
+            ORDER BY count DESC, login",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_influential_stargazers(
+        &self,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<UserScore>> {
+        let mut qb = QueryBuilder::new(
+            "SELECT username AS login, score
+            FROM user u
+            INNER JOIN user_influence ui ON (u.id = ui.user)
+            WHERE u.username IS NOT NULL",
+        );
+        filter.push_audience(&mut qb, "u.id");
+        qb.push(" ORDER BY score DESC, login");
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_communities(&self, page: Page, repos: u32) -> Result<Vec<Community>> {
+        let mut communities: Vec<Community> = query(
+            "SELECT id, members
+            FROM community
+            ORDER BY id
+            LIMIT ? OFFSET ?",
+        )
+        .bind(page.limit)
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| Community {
+            id: row.get("id"),
+            members: row.get("members"),
+            repos: Vec::new(),
+        })
+        .collect();
+
+        for c in communities.iter_mut() {
+            c.repos = query_as(
+                "SELECT full_name, cr.stargazers AS count
+                FROM repository r
+                INNER JOIN community_repos cr ON (r.id = cr.repository)
+                WHERE r.full_name IS NOT NULL
+                AND cr.community = ?
+                ORDER BY count DESC, full_name
+                LIMIT ?",
+            )
+            .bind(c.id)
+            .bind(repos)
+            .fetch_all(&self.pool)
+            .await?;
+        }
+
+        Ok(communities)
+    }
+
+    async fn get_top_topics(&self, filter: &Filter, page: Page) -> Result<Vec<TopicScore>> {
+        let mut qb = QueryBuilder::new(
+            "WITH starred AS (
+                SELECT user, repository
+                FROM user_repos
+                WHERE type = 'stargazer'
+                AND repository NOT IN (SELECT repository FROM target)",
+        );
+        if !filter.is_empty() {
+            filter.push_audience(&mut qb, "user");
+        }
+        qb.push(
+            "),
+            weight AS (
+                SELECT user, 1.0 / count(*) AS weight
+                FROM starred
+                GROUP BY user
+            )
+            SELECT topic, sum(w.weight) AS score
+            FROM starred s
+            INNER JOIN weight w ON (w.user = s.user)
+            INNER JOIN repository_topic rt ON (rt.repository = s.repository)
+            GROUP BY topic
+            ORDER BY score DESC, topic",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_top_languages(&self, filter: &Filter, page: Page) -> Result<Vec<LanguageScore>> {
+        let mut qb = QueryBuilder::new(
+            "WITH starred AS (
+                SELECT user, repository
+                FROM user_repos
+                WHERE type = 'stargazer'
+                AND repository NOT IN (SELECT repository FROM target)",
+        );
+        if !filter.is_empty() {
+            filter.push_audience(&mut qb, "user");
+        }
+        qb.push(
+            "),
+            weight AS (
+                SELECT user, 1.0 / count(*) AS weight
+                FROM starred
+                GROUP BY user
+            )
+            SELECT language, sum(w.weight) AS score
+            FROM starred s
+            INNER JOIN weight w ON (w.user = s.user)
+            INNER JOIN repository r ON (r.id = s.repository)
+            WHERE language IS NOT NULL
+            GROUP BY language
+            ORDER BY score DESC, language",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_stargazer_countries(
+        &self,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Vec<CountryCount>> {
+        let mut qb = QueryBuilder::new(
+            "SELECT country, count(*) as count
+            FROM user u
+            WHERE country IS NOT NULL",
+        );
+        filter.push_audience(&mut qb, "u.id");
+        qb.push(
+            " GROUP BY country
+            ORDER BY count DESC, country",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_stargazer_companies(&self, filter: &Filter) -> Result<Vec<String>> {
+        let mut qb = QueryBuilder::new(
+            "SELECT company
+            FROM user u
+            WHERE company IS NOT NULL",
+        );
+        filter.push_audience(&mut qb, "u.id");
+
+        Ok(qb
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| row.get("company"))
+            .collect())
+    }
+
+    async fn get_cohorts(&self, filter: &Filter, page: Page) -> Result<Vec<Cohort>> {
+        let mut qb = QueryBuilder::new(
+            "SELECT substr(s.date, 1, 7) AS month,
+                count(*) AS stargazers,
+                count(w.user) AS watchers,
+                count(f.user) AS forkers,
+                count(i.user) AS issue_authors,
+                count(c.user) AS contributors
+            FROM user_repos s
+            INNER JOIN repository r ON (r.id = s.repository)
+            LEFT JOIN user_repos w ON (
+                w.user = s.user AND w.repository = s.repository AND w.type = 'subscriber'
+            )
+            LEFT JOIN user_repos f ON (
+                f.user = s.user AND f.repository = s.repository AND f.type = 'forker'
+                AND f.date >= s.date
+            )
+            LEFT JOIN user_repos i ON (
+                i.user = s.user AND i.repository = s.repository AND i.type = 'issue_author'
+                AND i.date >= s.date
+            )
+            LEFT JOIN user_repos c ON (
+                c.user = s.user AND c.repository = s.repository AND c.type = 'contributor'
+            )
+            WHERE s.type = 'stargazer'
+            AND s.date IS NOT NULL",
+        );
+        match &filter.repo {
+            Some(repo) => qb.push(" AND r.full_name = ").push_bind(repo.clone()),
+            None => qb.push(" AND r.id IN (SELECT repository FROM target)"),
+        };
+        filter.push_dates(&mut qb, "s.date");
+        qb.push(
+            " GROUP BY month
+            ORDER BY month",
+        );
+        page.push(&mut qb);
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn get_user_detail(&self, username: &str) -> Result<Option<UserDetail>> {
+        let Some(mut user): Option<UserDetail> = query_as(
+            "SELECT id, username AS login, name, email, location, country, city, company, bio
+            FROM user
+            WHERE username = ?",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        user.target_stars = query_as(
+            "SELECT full_name, date
+            FROM user_repos ur
+            INNER JOIN repository r ON (r.id = ur.repository)
+            WHERE r.full_name IS NOT NULL
+            AND ur.user = ?
+            AND ur.type = 'stargazer'
+            AND r.id IN (SELECT repository FROM target)
+            ORDER BY full_name",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        user.starred = query_as(
+            "SELECT full_name, date
+            FROM user_repos ur
+            INNER JOIN repository r ON (r.id = ur.repository)
+            WHERE r.full_name IS NOT NULL
+            AND ur.user = ?
+            AND ur.type = 'stargazer'
+            AND r.id NOT IN (SELECT repository FROM target)
+            ORDER BY full_name",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        user.subscribed = query(
+            "SELECT full_name
+            FROM user_repos ur
+            INNER JOIN repository r ON (r.id = ur.repository)
+            WHERE r.full_name IS NOT NULL
+            AND ur.user = ?
+            AND ur.type = 'subscriber'
+            ORDER BY full_name",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("full_name"))
+        .collect();
+
+        user.following = query(
+            "SELECT username
+            FROM user_users uu
+            INNER JOIN user u ON (u.id = uu.linked)
+            WHERE u.username IS NOT NULL
+            AND uu.subject = ?
+            AND uu.type = 'follower'
+            ORDER BY username",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("username"))
+        .collect();
+
+        user.followers = query(
+            "SELECT username
+            FROM user_users uu
+            INNER JOIN user u ON (u.id = uu.subject)
+            WHERE u.username IS NOT NULL
+            AND uu.linked = ?
+            AND uu.type = 'follower'
+            ORDER BY username",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("username"))
+        .collect();
+
+        Ok(Some(user))
+    }
+
+    async fn get_repo_detail(
+        &self,
+        full_name: &str,
+        filter: &Filter,
+        page: Page,
+    ) -> Result<Option<RepoDetail>> {
+        let Some(mut repo): Option<RepoDetail> = query_as(
+            "SELECT id, full_name, stargazers, description, language,
+                id IN (SELECT repository FROM target) AS target
+            FROM repository
+            WHERE full_name = ?",
+        )
+        .bind(full_name)
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        repo.topics = query(
+            "SELECT topic
+            FROM repository_topic
+            WHERE repository = ?
+            ORDER BY topic",
+        )
+        .bind(repo.id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get("topic"))
+        .collect();
+
+        repo.snapshots = self.get_snapshots(repo.id).await?;
+
+        let mut qb = QueryBuilder::new(
+            "SELECT username AS login, date
+            FROM user_repos ur
+            INNER JOIN user u ON (u.id = ur.user)
+            WHERE u.username IS NOT NULL
+            AND ur.type = 'stargazer'
+            AND ur.repository = ",
+        );
+        qb.push_bind(repo.id);
+        filter.push_audience(&mut qb, "ur.user");
+        qb.push(" ORDER BY date, login");
+        page.lookahead().push(&mut qb);
+        repo.audience = Paginated::new(qb.build_query_as().fetch_all(&self.pool).await?, page);
+
+        let mut qb = QueryBuilder::<Sqlite>::new(
+            r#"SELECT COUNT(*) AS count
+            FROM user_repos ur
+            INNER JOIN user u ON (u.id = ur.user)
+            WHERE u.username IS NOT NULL
+            AND ur.type = 'stargazer'
+            AND ur.repository = "#,
+        );
+        qb.push_bind(repo.id);
+        filter.push_audience(&mut qb, r#"ur.user"#);
+        repo.audience_count = qb
+            .build()
+            .fetch_one(&self.pool)
+            .await?
+            .get::<i64, _>("count") as u32;
+
+        Ok(Some(repo))
+    }
+
+    async fn search_users(&self, words: &[String], page: Page) -> Result<Vec<UserHit>> {
+        let pattern = fts5_pattern(words);
+        if pattern.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(query_as(
+            "SELECT u.username AS login, u.name, u.company
+            FROM user_search s
+            INNER JOIN user u ON (u.id = s.rowid)
+            WHERE u.username IS NOT NULL
+            AND user_search MATCH ?
+            ORDER BY bm25(user_search, 10.0, 5.0, 2.0, 1.0), login
+            LIMIT ? OFFSET ?",
+        )
+        .bind(pattern)
+        .bind(page.limit)
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn search_repos(&self, words: &[String], page: Page) -> Result<Vec<RepoHit>> {
+        let pattern = fts5_pattern(words);
+        if pattern.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(query_as(
+            "SELECT r.full_name, r.description
+            FROM repository_search s
+            INNER JOIN repository r ON (r.id = s.rowid)
+            WHERE r.full_name IS NOT NULL
+            AND repository_search MATCH ?
+            ORDER BY bm25(repository_search, 10.0, 1.0, 5.0), r.full_name
+            LIMIT ? OFFSET ?",
+        )
+        .bind(pattern)
+        .bind(page.limit)
+        .bind(page.offset)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
@@ -1,95 +1,318 @@
-use anyhow::Result;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use futures::future::try_join_all;
+use serde::Serialize;
+use utoipa::ToSchema;
 
 use crate::{
-    db,
-    github::{create_client, get_repository},
+    company::resolve_orgs,
+    config::{Config, CrawlConfig, Stage},
+    db::{self, Batch, Storage},
+    geo::geocode,
+    github::{GitHub, Owner, Repository, StarredRepository, User, UserProfile},
 };
 
-pub async fn download(pat: &str, owner: &str, repo: &str, quick: bool) -> Result<()> {
-    let db = db::init("db/stargazers.sqlite").await?;
-    let client = create_client(pat)?;
-    let repo = get_repository(&client, owner, repo).await?;
+/// Everything fetched from GitHub about one stargazer, ready to be stored
+struct Crawled {
+    profile: Option<UserProfile>,
+    following: Vec<User>,
+    starred: Vec<StarredRepository>,
+    subscribed: Vec<Repository>,
+}
 
-    if !db::has_repo(&db, repo.id as i64).await? {
-        println!("Adding repo: {}", repo.full_name);
+/// How far a download has got, reported as it runs
+#[derive(Serialize, ToSchema, Clone, Default, Debug)]
+pub struct Progress {
+    /// What is being fetched: `repo`, `stargazers`, `forks`, `issues`,
+    /// `contributors`, or `done`
+    pub stage: String,
+    /// Stargazers crawled or skipped so far
+    pub stargazers: u64,
+    /// Stargazers listed on the repo
+    pub total: u64,
+    /// GitHub requests left before the rate limit resets
+    pub rate_remaining: Option<u64>,
+    /// Estimated seconds until every stargazer is crawled
+    pub eta_secs: Option<u64>,
+}
 
-        db::add_repo(
-            &db,
-            repo.id as i64,
-            &repo.full_name,
-            repo.stargazers_count as i64,
-        )
-        .await?;
+/// Called with the progress of a download after each step
+pub type Report<'a> = &'a (dyn Fn(&Progress) + Sync);
+
+pub async fn download(config: &Config, token: &str, targets: &[String]) -> Result<()> {
+    let db = db::connect(&config.db).await?;
+    let github = GitHub::new(&config.github, &config.retry, token)?;
+
+    for target in targets {
+        download_target(&*db, &github, &config.crawl, target, false, &|_| ()).await?;
     }
 
-    for s in repo.get_stargazers(&client).await? {
-        if !db::has_user(&db, s.user.id as i64).await? {
-            println!("Adding user: {}", s.user.login);
-
-            db::add_user(
-                &db,
-                s.user.id as i64,
-                &s.user.login,
-                s.user.name.as_deref(),
-                s.user.email.as_deref(),
-            )
-            .await?;
-        } else if quick {
-            println!("Skipping user: {}", s.user.login);
-            continue;
+    Ok(())
+}
+
+/// Download a target repo (owner/name) and its stargazers. An incremental
+/// download only fetches the pages of the repo's lists that changed since the
+/// last download, and skips stargazers that are already stored.
+pub async fn download_target(
+    db: &dyn Storage,
+    github: &GitHub,
+    crawl: &CrawlConfig,
+    target: &str,
+    incremental: bool,
+    report: Report<'_>,
+) -> Result<()> {
+    let step = |stage: &str, stargazers: u64, total: u64, eta_secs: Option<u64>| {
+        report(&Progress {
+            stage: stage.to_owned(),
+            stargazers,
+            total,
+            rate_remaining: github.rate_remaining(),
+            eta_secs,
+        })
+    };
+
+    step("repo", 0, 0, None);
+    let (owner, repo) = target
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Invalid target, expected owner/name: {target}"))?;
+    let repo = github.get_repository(owner, repo).await?;
+    let id = repo.id as i64;
+    let stage = |s| crawl.stages.contains(&s);
+    let quick = crawl.quick || incremental;
+    let known = |list| async move {
+        match incremental {
+            true => db.get_checkpoints(id, list).await,
+            false => Ok(Vec::new()),
         }
+    };
 
-        db::add_stargazer(&db, s.user.id as i64, repo.id as i64, &s.starred_at).await?;
-
-        // Get followers
-        for u in s.user.get_following(&client).await? {
-            println!("Adding following of {}: {}", s.user.login, u.login);
-
-            if !db::has_user(&db, u.id as i64).await? {
-                db::add_user(
-                    &db,
-                    u.id as i64,
-                    &u.login,
-                    u.name.as_deref(),
-                    u.email.as_deref(),
-                )
-                .await?;
-            }
+    println!("Adding repo: {}", repo.full_name);
+    let mut batch = Batch::default();
+    add_repo(&mut batch, &repo);
+    db.write(&batch).await?;
+    db.add_target(repo.id as i64).await?;
+    db.add_snapshot(repo.id as i64, repo.stargazers_count as i64)
+        .await?;
 
-            db::add_follower(&db, s.user.id as i64, u.id as i64).await?;
+    let stargazers = repo
+        .get_stargazers(github, &known("stargazers").await?)
+        .await?;
+    let total = stargazers.items.len() as u64;
+    let started = Instant::now();
+    let mut done = 0;
+    step("stargazers", done, total, None);
+    for chunk in stargazers.items.chunks(crawl.concurrency.max(1)) {
+        // Work out which stargazers to crawl before fetching them together
+        let ids: Vec<i64> = chunk.iter().map(|s| s.user.id as i64).collect();
+        // Users already stored as someone's followee, contributor or fork
+        // owner have no profile yet, so are still new here
+        let profiled = db.get_profiled_users(&ids).await?;
+        let mut crawling = Vec::new();
+        for s in chunk {
+            let new = !profiled.contains(&(s.user.id as i64));
+            if !new && quick {
+                println!("Skipping user: {}", s.user.login);
+                continue;
+            }
+            crawling.push((s, new));
         }
 
-        // Get starred repos
-        for r in s.user.get_starred(&client).await? {
-            println!(
-                "Adding starred repo of {}: {}",
-                s.user.login, r.repo.full_name
-            );
+        let crawled = try_join_all(
+            crawling
+                .iter()
+                .map(|(s, new)| crawl_user(github, crawl, &s.user, *new)),
+        )
+        .await?;
 
-            if !db::has_repo(&db, r.repo.id as i64).await? {
-                db::add_repo(
-                    &db,
-                    r.repo.id as i64,
-                    &r.repo.full_name,
-                    r.repo.stargazers_count as i64,
-                )
-                .await?;
+        // Store the whole chunk in one transaction, so an interrupted
+        // download never leaves a stargazer half written
+        let mut batch = Batch::default();
+        for ((s, new), c) in crawling.iter().zip(crawled) {
+            if *new {
+                println!("Adding user: {}", s.user.login);
             }
+            add_user(&mut batch, &s.user);
 
-            db::add_stargazer(&db, s.user.id as i64, r.repo.id as i64, &r.starred_at).await?;
-        }
+            if let Some(profile) = &c.profile {
+                let place = profile.location.as_deref().and_then(geocode);
+                batch.set_profile(
+                    s.user.id as i64,
+                    profile.location.as_deref(),
+                    place.as_ref(),
+                    profile.company.as_deref(),
+                    profile.bio.as_deref(),
+                    profile.followers,
+                );
+                if let Some(company) = &profile.company {
+                    resolve_orgs(db, github, company).await?;
+                }
+            }
+
+            batch.add_stargazer(s.user.id as i64, repo.id as i64, &s.starred_at);
+
+            // Get followers
+            for u in &c.following {
+                println!("Adding following of {}: {}", s.user.login, u.login);
+                add_user(&mut batch, u);
+                batch.add_follower(s.user.id as i64, u.id as i64);
+            }
 
-        // Get subscribed repos
-        for r in s.user.get_subscribed(&client).await? {
-            println!("Adding starred repo of {}: {}", s.user.login, r.full_name);
+            // Get starred repos
+            for r in &c.starred {
+                println!(
+                    "Adding starred repo of {}: {}",
+                    s.user.login, r.repo.full_name
+                );
+                add_repo(&mut batch, &r.repo);
+                batch.add_stargazer(s.user.id as i64, r.repo.id as i64, &r.starred_at);
+            }
 
-            if !db::has_repo(&db, r.id as i64).await? {
-                db::add_repo(&db, r.id as i64, &r.full_name, r.stargazers_count as i64).await?;
+            // Get subscribed repos
+            for r in &c.subscribed {
+                println!("Adding starred repo of {}: {}", s.user.login, r.full_name);
+                add_repo(&mut batch, r);
+                batch.add_subscriber(s.user.id as i64, r.id as i64);
             }
+        }
+        if !batch.is_empty() {
+            db.write(&batch).await?;
+        }
+
+        done += chunk.len() as u64;
+        let eta = started.elapsed().as_secs_f64() / done as f64 * (total - done) as f64;
+        step("stargazers", done, total, Some(eta.round() as u64));
+    }
+    db.set_checkpoints(id, "stargazers", &stargazers.checkpoints)
+        .await?;
+
+    // Get activity on the target repo, to see which stargazers engage further
+    let mut batch = Batch::default();
+    let mut checkpoints = Vec::new();
+    if stage(Stage::Forks) {
+        step("forks", done, total, None);
+        let forks = repo.get_forks(github, &known("forks").await?).await?;
+        for f in &forks.items {
+            println!("Adding fork: {}", f.full_name);
+            add_owner(&mut batch, &f.owner);
+            batch.add_activity(
+                f.owner.id as i64,
+                repo.id as i64,
+                "forker",
+                f.created_at.as_ref(),
+            );
+        }
+        checkpoints.push(("forks", forks.checkpoints));
+    }
+
+    if stage(Stage::Issues) {
+        step("issues", done, total, None);
+        let issues = repo.get_issues(github, &known("issues").await?).await?;
+        for i in &issues.items {
+            let Some(user) = &i.user else {
+                continue;
+            };
+
+            println!("Adding issue #{}: {}", i.number, i.title);
+            add_owner(&mut batch, user);
+            batch.add_activity(
+                user.id as i64,
+                repo.id as i64,
+                "issue_author",
+                Some(&i.created_at),
+            );
+        }
+        checkpoints.push(("issues", issues.checkpoints));
+    }
 
-            db::add_subscriber(&db, s.user.id as i64, r.id as i64).await?;
+    if stage(Stage::Contributors) {
+        step("contributors", done, total, None);
+        let contributors = repo
+            .get_contributors(github, &known("contributors").await?)
+            .await?;
+        for c in &contributors.items {
+            println!("Adding contributor: {}", c.login);
+            batch.add_user(c.id as i64, &c.login, None, None);
+            batch.add_activity(c.id as i64, repo.id as i64, "contributor", None);
         }
+        checkpoints.push(("contributors", contributors.checkpoints));
+    }
+    db.write(&batch).await?;
+
+    // Only once the pages' contents are stored, so an interrupted download
+    // fetches them again
+    for (list, pages) in checkpoints {
+        db.set_checkpoints(id, list, &pages).await?;
     }
+    step("done", done, total, None);
 
     Ok(())
 }
+
+/// Fetch the enabled stages for one stargazer. Profiles are only fetched for
+/// users whose profile hasn't been fetched before.
+async fn crawl_user(
+    github: &GitHub,
+    crawl: &CrawlConfig,
+    user: &User,
+    new: bool,
+) -> Result<Crawled> {
+    let stage = |s| crawl.stages.contains(&s);
+
+    let profile = if new && stage(Stage::Profiles) {
+        Some(user.get_profile(github).await?)
+    } else {
+        None
+    };
+    let following = if stage(Stage::Following) {
+        user.get_following(github).await?
+    } else {
+        Vec::new()
+    };
+    let starred = if stage(Stage::Starred) {
+        user.get_starred(github).await?
+    } else {
+        Vec::new()
+    };
+    let subscribed = if stage(Stage::Subscribed) {
+        user.get_subscribed(github).await?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Crawled {
+        profile,
+        following,
+        starred,
+        subscribed,
+    })
+}
+
+fn add_owner(batch: &mut Batch, owner: &Owner) {
+    batch.add_user(
+        owner.id as i64,
+        &owner.login,
+        owner.name.as_deref(),
+        owner.email.as_deref(),
+    );
+}
+
+fn add_user(batch: &mut Batch, user: &User) {
+    batch.add_user(
+        user.id as i64,
+        &user.login,
+        user.name.as_deref(),
+        user.email.as_deref(),
+    );
+}
+
+fn add_repo(batch: &mut Batch, repo: &Repository) {
+    batch.add_repo(
+        repo.id as i64,
+        &repo.full_name,
+        repo.stargazers_count as i64,
+        repo.description.as_deref(),
+        repo.language.as_deref(),
+        &repo.topics,
+    );
+}
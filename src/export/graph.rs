@@ -0,0 +1,457 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use super::{escape_xml, open_output};
+use crate::db::{self, Storage};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum GraphFormat {
+    Graphml,
+    Gexf,
+    Dot,
+    Json,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Node {
+    User {
+        id: String,
+        login: Option<String>,
+        name: Option<String>,
+        email: Option<String>,
+        location: Option<String>,
+        company: Option<String>,
+        bio: Option<String>,
+        country: Option<String>,
+    },
+    Repo {
+        id: String,
+        full_name: Option<String>,
+        stargazers: i64,
+        description: Option<String>,
+        language: Option<String>,
+        topics: Vec<String>,
+    },
+}
+
+/// Node attributes after `type`, with their GraphML/GEXF types. Their order
+/// gives the GEXF attribute ids, so new ones go at the end.
+const NODE_ATTRIBUTES: &[(&str, &str)] = &[
+    ("name", "string"),
+    ("email", "string"),
+    ("stargazers", "long"),
+    ("location", "string"),
+    ("company", "string"),
+    ("bio", "string"),
+    ("country", "string"),
+    ("description", "string"),
+    ("language", "string"),
+    ("topics", "string"),
+];
+
+#[derive(Serialize, Debug)]
+struct Edge {
+    source: String,
+    target: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    date: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+impl Node {
+    fn id(&self) -> &str {
+        match self {
+            Node::User { id, .. } | Node::Repo { id, .. } => id,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Node::User { .. } => "user",
+            Node::Repo { .. } => "repo",
+        }
+    }
+
+    /// Attributes that have a value, named as in [`NODE_ATTRIBUTES`]
+    fn attributes(&self) -> Vec<(&'static str, String)> {
+        let mut attributes = Vec::new();
+        let mut add = |name, value: &Option<String>| {
+            if let Some(value) = value {
+                attributes.push((name, value.clone()));
+            }
+        };
+        match self {
+            Node::User {
+                name,
+                email,
+                location,
+                company,
+                bio,
+                country,
+                ..
+            } => {
+                add("name", name);
+                add("email", email);
+                add("location", location);
+                add("company", company);
+                add("bio", bio);
+                add("country", country);
+            }
+            Node::Repo {
+                stargazers,
+                description,
+                language,
+                topics,
+                ..
+            } => {
+                add("description", description);
+                add("language", language);
+                attributes.push(("stargazers", stargazers.to_string()));
+                if !topics.is_empty() {
+                    attributes.push(("topics", topics.join(",")));
+                }
+            }
+        }
+        attributes
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            // Names taken over by another account are unknown until the
+            // user or repo is seen again
+            Node::User { id, login, .. } => login.as_deref().unwrap_or(id),
+            Node::Repo { id, full_name, .. } => full_name.as_deref().unwrap_or(id),
+        }
+    }
+}
+
+pub async fn export_graph(
+    db_url: &str,
+    format: GraphFormat,
+    out: Option<&Path>,
+    min_degree: usize,
+    repos: &[String],
+) -> Result<()> {
+    let db = db::connect(db_url).await?;
+    let graph = load_graph(&*db, min_degree, repos).await?;
+    let mut w = open_output(out)?;
+
+    match format {
+        GraphFormat::Graphml => write_graphml(&mut w, &graph)?,
+        GraphFormat::Gexf => write_gexf(&mut w, &graph)?,
+        GraphFormat::Dot => write_dot(&mut w, &graph)?,
+        GraphFormat::Json => serde_json::to_writer_pretty(&mut w, &graph)?,
+    }
+
+    w.flush()?;
+    Ok(())
+}
+
+async fn load_graph(db: &dyn Storage, min_degree: usize, repos: &[String]) -> Result<Graph> {
+    let mut repo_nodes = db.get_repos().await?;
+    if !repos.is_empty() {
+        repo_nodes.retain(|r| r.full_name.as_ref().is_some_and(|n| repos.contains(n)));
+        for name in repos {
+            if !repo_nodes
+                .iter()
+                .any(|r| r.full_name.as_ref() == Some(name))
+            {
+                bail!("Unknown repository: {name}");
+            }
+        }
+    }
+    let repo_ids: HashSet<i64> = repo_nodes.iter().map(|r| r.id).collect();
+
+    let mut edges = Vec::new();
+    for e in db.get_user_repo_edges().await? {
+        if !repo_ids.contains(&e.target) {
+            continue;
+        }
+
+        let kind = match e.kind.as_str() {
+            "stargazer" => "starred",
+            "subscriber" => "subscribed",
+            _ => continue,
+        };
+        edges.push(Edge {
+            source: format!("u{}", e.source),
+            target: format!("r{}", e.target),
+            kind,
+            date: e.date,
+        });
+    }
+
+    // With a repo subset, only keep users that are connected to it
+    let linked_users: Option<HashSet<String>> = if repos.is_empty() {
+        None
+    } else {
+        Some(edges.iter().map(|e| e.source.clone()).collect())
+    };
+
+    for e in db.get_user_user_edges().await? {
+        let source = format!("u{}", e.source);
+        let target = format!("u{}", e.target);
+        if let Some(users) = &linked_users {
+            if !users.contains(&source) || !users.contains(&target) {
+                continue;
+            }
+        }
+
+        edges.push(Edge {
+            source,
+            target,
+            kind: "follows",
+            date: None,
+        });
+    }
+
+    let mut nodes: Vec<Node> = db
+        .get_users()
+        .await?
+        .into_iter()
+        .map(|u| Node::User {
+            id: format!("u{}", u.id),
+            login: u.username,
+            name: u.name,
+            email: u.email,
+            location: u.location,
+            company: u.company,
+            bio: u.bio,
+            country: u.country,
+        })
+        .filter(|n| linked_users.as_ref().is_none_or(|u| u.contains(n.id())))
+        .collect();
+    let mut topics = db.get_repo_topics().await?;
+    nodes.extend(repo_nodes.into_iter().map(|r| Node::Repo {
+        id: format!("r{}", r.id),
+        full_name: r.full_name,
+        stargazers: r.stargazers,
+        description: r.description,
+        language: r.language,
+        topics: topics.remove(&r.id).unwrap_or_default(),
+    }));
+
+    if min_degree > 0 {
+        let mut degree: HashMap<&str, usize> = HashMap::new();
+        for e in &edges {
+            *degree.entry(&e.source).or_default() += 1;
+            *degree.entry(&e.target).or_default() += 1;
+        }
+
+        let keep: HashSet<String> = nodes
+            .iter()
+            .filter(|n| degree.get(n.id()).copied().unwrap_or(0) >= min_degree)
+            .map(|n| n.id().to_owned())
+            .collect();
+
+        nodes.retain(|n| keep.contains(n.id()));
+        edges.retain(|e| keep.contains(&e.source) && keep.contains(&e.target));
+    }
+
+    Ok(Graph { nodes, edges })
+}
+
+fn write_graphml_header<W: Write>(w: &mut W) -> Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        w,
+        r#"  <key id="type" for="node" attr.name="type" attr.type="string"/>"#
+    )?;
+    writeln!(
+        w,
+        r#"  <key id="label" for="node" attr.name="label" attr.type="string"/>"#
+    )?;
+    for (name, kind) in NODE_ATTRIBUTES {
+        writeln!(
+            w,
+            r#"  <key id="{name}" for="node" attr.name="{name}" attr.type="{kind}"/>"#
+        )?;
+    }
+    writeln!(
+        w,
+        r#"  <key id="edge_type" for="edge" attr.name="type" attr.type="string"/>"#
+    )?;
+    writeln!(
+        w,
+        r#"  <key id="date" for="edge" attr.name="date" attr.type="string"/>"#
+    )?;
+    writeln!(w, r#"  <graph id="stargazers" edgedefault="directed">"#)?;
+    Ok(())
+}
+
+fn write_gexf_header<W: Write>(w: &mut W) -> Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(w, r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#)?;
+    writeln!(w, r#"  <graph defaultedgetype="directed" mode="static">"#)?;
+    writeln!(w, r#"    <attributes class="node">"#)?;
+    writeln!(w, r#"      <attribute id="0" title="type" type="string"/>"#)?;
+    for (i, (name, kind)) in NODE_ATTRIBUTES.iter().enumerate() {
+        writeln!(
+            w,
+            r#"      <attribute id="{}" title="{name}" type="{kind}"/>"#,
+            i + 1
+        )?;
+    }
+    writeln!(w, "    </attributes>")?;
+    writeln!(w, r#"    <attributes class="edge">"#)?;
+    writeln!(w, r#"      <attribute id="0" title="date" type="string"/>"#)?;
+    writeln!(w, "    </attributes>")?;
+    Ok(())
+}
+
+/// GEXF id of a node attribute
+fn gexf_id(name: &str) -> usize {
+    NODE_ATTRIBUTES
+        .iter()
+        .position(|(n, _)| *n == name)
+        .map_or(0, |i| i + 1)
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_graphml<W: Write>(w: &mut W, graph: &Graph) -> Result<()> {
+    write_graphml_header(w)?;
+
+    for n in &graph.nodes {
+        writeln!(w, r#"    <node id="{}">"#, n.id())?;
+        writeln!(w, r#"      <data key="type">{}</data>"#, n.kind())?;
+        writeln!(
+            w,
+            r#"      <data key="label">{}</data>"#,
+            escape_xml(n.label())
+        )?;
+        for (name, value) in n.attributes() {
+            writeln!(
+                w,
+                r#"      <data key="{name}">{}</data>"#,
+                escape_xml(&value)
+            )?;
+        }
+        writeln!(w, "    </node>")?;
+    }
+
+    for e in &graph.edges {
+        writeln!(
+            w,
+            r#"    <edge source="{}" target="{}">"#,
+            e.source, e.target
+        )?;
+        writeln!(w, r#"      <data key="edge_type">{}</data>"#, e.kind)?;
+        if let Some(date) = &e.date {
+            writeln!(w, r#"      <data key="date">{}</data>"#, escape_xml(date))?;
+        }
+        writeln!(w, "    </edge>")?;
+    }
+
+    writeln!(w, "  </graph>")?;
+    writeln!(w, "</graphml>")?;
+    Ok(())
+}
+
+fn write_gexf<W: Write>(w: &mut W, graph: &Graph) -> Result<()> {
+    write_gexf_header(w)?;
+
+    writeln!(w, "    <nodes>")?;
+    for n in &graph.nodes {
+        writeln!(
+            w,
+            r#"      <node id="{}" label="{}">"#,
+            n.id(),
+            escape_xml(n.label())
+        )?;
+        writeln!(w, "        <attvalues>")?;
+        writeln!(w, r#"          <attvalue for="0" value="{}"/>"#, n.kind())?;
+        for (name, value) in n.attributes() {
+            writeln!(
+                w,
+                r#"          <attvalue for="{}" value="{}"/>"#,
+                gexf_id(name),
+                escape_xml(&value)
+            )?;
+        }
+        writeln!(w, "        </attvalues>")?;
+        writeln!(w, "      </node>")?;
+    }
+    writeln!(w, "    </nodes>")?;
+
+    writeln!(w, "    <edges>")?;
+    for (i, e) in graph.edges.iter().enumerate() {
+        write!(
+            w,
+            r#"      <edge id="{i}" source="{}" target="{}" label="{}""#,
+            e.source, e.target, e.kind
+        )?;
+        match &e.date {
+            Some(date) => {
+                writeln!(w, ">")?;
+                writeln!(w, "        <attvalues>")?;
+                writeln!(
+                    w,
+                    r#"          <attvalue for="0" value="{}"/>"#,
+                    escape_xml(date)
+                )?;
+                writeln!(w, "        </attvalues>")?;
+                writeln!(w, "      </edge>")?;
+            }
+            None => writeln!(w, "/>")?,
+        }
+    }
+    writeln!(w, "    </edges>")?;
+
+    writeln!(w, "  </graph>")?;
+    writeln!(w, "</gexf>")?;
+    Ok(())
+}
+
+fn write_dot<W: Write>(w: &mut W, graph: &Graph) -> Result<()> {
+    writeln!(w, "digraph stargazers {{")?;
+
+    for n in &graph.nodes {
+        let shape = match n {
+            Node::User { .. } => "ellipse",
+            Node::Repo { .. } => "box",
+        };
+        writeln!(
+            w,
+            r#"  "{}" [label="{}", type="{}", shape={shape}];"#,
+            n.id(),
+            escape_dot(n.label()),
+            n.kind()
+        )?;
+    }
+
+    for e in &graph.edges {
+        write!(
+            w,
+            r#"  "{}" -> "{}" [type="{}""#,
+            e.source, e.target, e.kind
+        )?;
+        if let Some(date) = &e.date {
+            write!(w, r#", date="{}""#, escape_dot(date))?;
+        }
+        writeln!(w, "];")?;
+    }
+
+    writeln!(w, "}}")?;
+    Ok(())
+}
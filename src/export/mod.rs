@@ -0,0 +1,31 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+
+mod graph;
+mod table;
+pub use self::{
+    graph::{export_graph, GraphFormat},
+    table::{export_table, Query, Table, TableFormat},
+};
+
+/// Open the export destination, falling back to stdout when no path is given
+fn open_output(out: Option<&Path>) -> Result<Box<dyn Write + Send>> {
+    Ok(match out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    })
+}
+
+/// Escape text for XML, which also covers HTML and SVG
+pub fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,309 @@
+use std::{io::Write, path::Path, sync::Arc};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use futures::TryStreamExt;
+use parquet::{
+    basic::{ConvertedType, Repetition, Type as PhysicalType},
+    data_type::{ByteArray, ByteArrayType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+use serde::{ser::SerializeMap, Serializer};
+
+use super::open_output;
+use crate::db::{self, ColumnType, Export, Value};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Table {
+    Users,
+    Repos,
+    Stargazers,
+    Edges,
+    Snapshots,
+    Targets,
+    Topics,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Query {
+    /// Repos ranked by how many of our users starred them
+    TopRepos,
+    /// Stars per day on each target repo, with a running total
+    StarTimeline,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TableFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+/// Rows buffered into each Parquet row group
+const ROW_GROUP_SIZE: usize = 10_000;
+
+impl From<Table> for Export {
+    fn from(table: Table) -> Export {
+        match table {
+            Table::Users => Export::Users,
+            Table::Repos => Export::Repos,
+            Table::Stargazers => Export::Stargazers,
+            Table::Edges => Export::Edges,
+            Table::Snapshots => Export::Snapshots,
+            Table::Targets => Export::Targets,
+            Table::Topics => Export::Topics,
+        }
+    }
+}
+
+impl From<Query> for Export {
+    fn from(query: Query) -> Export {
+        match query {
+            Query::TopRepos => Export::TopRepos,
+            Query::StarTimeline => Export::StarTimeline,
+        }
+    }
+}
+
+/// Stream a table or canned query to a file or stdout, without loading it
+/// into memory
+pub async fn export_table(
+    db_url: &str,
+    export: Export,
+    format: TableFormat,
+    out: Option<&Path>,
+) -> Result<()> {
+    let db = db::connect(db_url).await?;
+    let w = open_output(out)?;
+    let columns = export.columns();
+
+    let mut writer: Box<dyn RowWriter> = match format {
+        TableFormat::Csv => Box::new(CsvWriter::new(w, columns)?),
+        TableFormat::Jsonl => Box::new(JsonlWriter { w, columns }),
+        TableFormat::Parquet => Box::new(ParquetWriter::new(w, columns)?),
+    };
+
+    let mut rows = db.export(export);
+    while let Some(row) = rows.try_next().await? {
+        writer.write(row)?;
+    }
+    writer.finish()
+}
+
+trait RowWriter {
+    fn write(&mut self, row: Vec<Value>) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+struct CsvWriter {
+    w: csv::Writer<Box<dyn Write + Send>>,
+}
+
+impl CsvWriter {
+    fn new(w: Box<dyn Write + Send>, columns: &[(&str, ColumnType)]) -> Result<CsvWriter> {
+        let mut w = csv::Writer::from_writer(w);
+        w.write_record(columns.iter().map(|(name, _)| name))?;
+        Ok(CsvWriter { w })
+    }
+}
+
+impl RowWriter for CsvWriter {
+    fn write(&mut self, row: Vec<Value>) -> Result<()> {
+        self.w
+            .write_record(row.into_iter().map(|value| match value {
+                Value::Null => String::new(),
+                Value::Integer(i) => i.to_string(),
+                Value::Text(s) => s,
+            }))?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+/// One JSON object per line, keyed by column name
+struct JsonlWriter {
+    w: Box<dyn Write + Send>,
+    columns: &'static [(&'static str, ColumnType)],
+}
+
+impl RowWriter for JsonlWriter {
+    fn write(&mut self, row: Vec<Value>) -> Result<()> {
+        // Keep the columns in order, as `serde_json::Map` would sort them
+        let mut object = serde_json::Serializer::new(&mut self.w);
+        let mut map = object.serialize_map(Some(row.len()))?;
+        for ((name, _), value) in self.columns.iter().zip(&row) {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()?;
+
+        writeln!(self.w)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+/// Buffers rows and writes them a row group at a time, as Parquet is
+/// columnar
+struct ParquetWriter {
+    w: SerializedFileWriter<Box<dyn Write + Send>>,
+    columns: &'static [(&'static str, ColumnType)],
+    rows: Vec<Vec<Value>>,
+}
+
+impl ParquetWriter {
+    fn new(
+        w: Box<dyn Write + Send>,
+        columns: &'static [(&'static str, ColumnType)],
+    ) -> Result<ParquetWriter> {
+        let fields = columns
+            .iter()
+            .map(|(name, kind)| {
+                let field = match kind {
+                    ColumnType::Integer => Type::primitive_type_builder(name, PhysicalType::INT64),
+                    ColumnType::Text => {
+                        Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                            .with_converted_type(ConvertedType::UTF8)
+                    }
+                };
+                Ok(Arc::new(
+                    field.with_repetition(Repetition::OPTIONAL).build()?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let schema = Type::group_type_builder("export")
+            .with_fields(fields)
+            .build()?;
+
+        let properties = WriterProperties::builder().build();
+        Ok(ParquetWriter {
+            w: SerializedFileWriter::new(w, Arc::new(schema), Arc::new(properties))?,
+            columns,
+            rows: Vec::with_capacity(ROW_GROUP_SIZE),
+        })
+    }
+
+    fn write_row_group(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut group = self.w.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = group.next_column()? {
+            let values = self.rows.iter().map(|row| &row[index]);
+            // Nulls are marked by a definition level of 0 and have no value
+            let levels: Vec<i16> = values
+                .clone()
+                .map(|value| i16::from(*value != Value::Null))
+                .collect();
+
+            match self.columns[index].1 {
+                ColumnType::Integer => {
+                    let data: Vec<i64> = values
+                        .filter_map(|value| match value {
+                            Value::Integer(i) => Some(*i),
+                            _ => None,
+                        })
+                        .collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnType::Text => {
+                    let data: Vec<ByteArray> = values
+                        .filter_map(|value| match value {
+                            Value::Text(s) => Some(ByteArray::from(s.as_str())),
+                            _ => None,
+                        })
+                        .collect();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+            }
+
+            column.close()?;
+            index += 1;
+        }
+        group.close()?;
+
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+impl RowWriter for ParquetWriter {
+    fn write(&mut self, row: Vec<Value>) -> Result<()> {
+        self.rows.push(row);
+        if self.rows.len() >= ROW_GROUP_SIZE {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.write_row_group()?;
+        self.w.into_inner()?.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs::File};
+
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::Field,
+    };
+
+    use super::*;
+
+    #[test]
+    fn parquet_round_trip() -> Result<()> {
+        let path = env::temp_dir().join(format!("stargazers-test-{}.parquet", std::process::id()));
+        let mut writer = Box::new(ParquetWriter::new(
+            open_output(Some(&path))?,
+            Export::Snapshots.columns(),
+        )?);
+        for i in 0..ROW_GROUP_SIZE as i64 + 1 {
+            writer.write(vec![
+                Value::Integer(1),
+                Value::Null,
+                Value::Integer(i),
+                Value::Text(format!("day {i}")),
+            ])?;
+        }
+        writer.finish()?;
+
+        let reader = SerializedFileReader::new(File::open(&path)?)?;
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let rows: Vec<_> = reader.get_row_iter(None)?.collect::<Result<_, _>>()?;
+        std::fs::remove_file(path)?;
+
+        assert_eq!(rows.len(), ROW_GROUP_SIZE + 1);
+        let last: Vec<&Field> = rows[ROW_GROUP_SIZE]
+            .get_column_iter()
+            .map(|(_, f)| f)
+            .collect();
+        assert_eq!(
+            last,
+            [
+                &Field::Long(1),
+                &Field::Null,
+                &Field::Long(ROW_GROUP_SIZE as i64),
+                &Field::Str(format!("day {ROW_GROUP_SIZE}")),
+            ]
+        );
+        Ok(())
+    }
+}
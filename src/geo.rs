@@ -0,0 +1,316 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::Result;
+
+use crate::db;
+
+const GAZETTEER_TSV: &str = include_str!("../data/gazetteer.tsv");
+
+/// Longest run of words we try to match inside a free text location
+const MAX_NGRAM: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub struct Place {
+    pub country: String,
+    pub city: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Country,
+    Region,
+    City,
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind: Kind,
+    name: String,
+    country: String,
+}
+
+#[derive(Debug)]
+struct Gazetteer {
+    aliases: HashMap<String, Vec<Entry>>,
+    countries: HashMap<String, String>,
+}
+
+/// Match a free text profile location (e.g. "SF", "Berlin, DE") to a country
+/// and, where possible, a city, using the bundled gazetteer.
+pub fn geocode(location: &str) -> Option<Place> {
+    let gazetteer = gazetteer();
+
+    // A flag emoji is as good as a country code
+    if let Some(code) = flag_country(location) {
+        if let Some(country) = gazetteer.countries.get(&code) {
+            return Some(Place {
+                country: country.clone(),
+                city: None,
+            });
+        }
+    }
+
+    let mut cities: Vec<&Entry> = Vec::new();
+    let mut parts: Vec<Vec<&str>> = Vec::new();
+
+    for (i, part) in split(location).iter().enumerate() {
+        let mut matches = gazetteer.lookup(part);
+        if matches.is_empty() {
+            matches = gazetteer.search(part);
+        }
+
+        // In "Cambridge, MA" the trailing code is far more likely to be a US
+        // state than a country, whereas a lone "MA" is taken as Morocco.
+        let order = if i > 0 && part.chars().count() <= 2 {
+            [Kind::Region, Kind::Country, Kind::City]
+        } else {
+            [Kind::Country, Kind::Region, Kind::City]
+        };
+
+        let mut candidates: Vec<&str> = Vec::new();
+        for kind in order {
+            for e in matches.iter().filter(|e| e.kind == kind) {
+                if !candidates.contains(&e.country.as_str()) {
+                    candidates.push(&e.country);
+                }
+            }
+        }
+        if !candidates.is_empty() {
+            parts.push(candidates);
+        }
+
+        cities.extend(matches.into_iter().filter(|e| e.kind == Kind::City));
+    }
+
+    // Pick the country mentioned by the most parts, e.g. "Berlin, DE" agrees
+    // on Germany. Ties go to the last part, as locations usually end with the
+    // country.
+    let mut best: Option<(&str, usize)> = None;
+    for candidates in parts.iter().rev() {
+        for code in candidates {
+            let score = parts.iter().filter(|p| p.contains(code)).count();
+            if best.is_none_or(|(_, s)| score > s) {
+                best = Some((code, score));
+            }
+        }
+    }
+    let (code, _) = best?;
+
+    Some(Place {
+        country: gazetteer.countries.get(code)?.clone(),
+        city: cities
+            .iter()
+            .find(|c| c.country == code)
+            .map(|c| c.name.clone()),
+    })
+}
+
+/// ISO 3166-1 alpha-2 code of a country name stored by [`geocode`]
+pub fn country_code(name: &str) -> Option<&'static str> {
+    gazetteer()
+        .countries
+        .iter()
+        .find(|(_, country)| *country == name)
+        .map(|(code, _)| code.as_str())
+}
+
+/// Re-run geocoding over every stored location, e.g. after the gazetteer has
+/// been updated
+pub async fn compute_geo(db_url: &str) -> Result<()> {
+    let db = db::connect(db_url).await?;
+
+    let mut found = 0;
+    let locations = db.get_user_locations().await?;
+    for (id, location) in &locations {
+        let place = geocode(location);
+        if place.is_some() {
+            found += 1;
+        }
+        db.set_user_place(*id, place.as_ref()).await?;
+    }
+
+    println!("Geocoded {found} of {} locations", locations.len());
+    Ok(())
+}
+
+fn gazetteer() -> &'static Gazetteer {
+    static GAZETTEER: OnceLock<Gazetteer> = OnceLock::new();
+    GAZETTEER.get_or_init(|| Gazetteer::parse(GAZETTEER_TSV))
+}
+
+impl Gazetteer {
+    fn parse(tsv: &str) -> Gazetteer {
+        let mut aliases: HashMap<String, Vec<Entry>> = HashMap::new();
+        let mut countries = HashMap::new();
+
+        for line in tsv.lines() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [kind, name, country, names] = fields[..] else {
+                panic!("Invalid gazetteer line: {line}");
+            };
+
+            let kind = match kind {
+                "country" => Kind::Country,
+                "region" => Kind::Region,
+                "city" => Kind::City,
+                _ => panic!("Invalid gazetteer kind: {kind}"),
+            };
+
+            let mut keys: Vec<String> = names.split(',').map(normalise).collect();
+            keys.push(normalise(name));
+            if kind == Kind::Country {
+                keys.push(normalise(country));
+                countries.insert(country.to_owned(), name.to_owned());
+            }
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                aliases.entry(key).or_default().push(Entry {
+                    kind,
+                    name: name.to_owned(),
+                    country: country.to_owned(),
+                });
+            }
+        }
+
+        Gazetteer { aliases, countries }
+    }
+
+    /// Match a whole location part, e.g. "ca" in "Toronto, CA"
+    fn lookup(&self, part: &str) -> Vec<&Entry> {
+        self.aliases
+            .get(part)
+            .map(|entries| entries.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Match runs of words within a part, e.g. "berlin" in "living in berlin".
+    /// Short aliases like "in" or "me" are skipped as they are mostly noise.
+    fn search(&self, part: &str) -> Vec<&Entry> {
+        let words: Vec<&str> = part.split_whitespace().collect();
+        let mut found = Vec::new();
+
+        for n in (1..=MAX_NGRAM.min(words.len())).rev() {
+            for window in words.windows(n) {
+                let phrase = window.join(" ");
+                if phrase.len() <= 3 {
+                    continue;
+                }
+                if let Some(entries) = self.aliases.get(&phrase) {
+                    found.extend(entries);
+                }
+            }
+        }
+
+        found
+    }
+}
+
+/// Lower case and drop dots, so "U.S.A." matches "usa"
+fn normalise(s: &str) -> String {
+    s.trim().to_lowercase().replace('.', "")
+}
+
+/// Break a location into parts on punctuation and symbols, e.g.
+/// "Berlin, DE 🌍" becomes `["berlin", "de"]`
+fn split(location: &str) -> Vec<String> {
+    normalise(location)
+        .split(|c: char| !(c.is_alphanumeric() || c.is_whitespace() || c == '\'' || c == '-'))
+        .map(|part| part.split_whitespace().collect::<Vec<_>>().join(" "))
+        .map(|part| part.trim_matches('-').trim().to_owned())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// Decode the first flag emoji (a pair of regional indicator symbols) into an
+/// ISO 3166-1 alpha-2 country code
+fn flag_country(s: &str) -> Option<String> {
+    const REGIONAL_A: u32 = 0x1F1E6;
+
+    let letters: Vec<char> = s
+        .chars()
+        .filter_map(|c| {
+            let offset = (c as u32).checked_sub(REGIONAL_A)?;
+            (offset < 26).then(|| (b'A' + offset as u8) as char)
+        })
+        .take(2)
+        .collect();
+
+    (letters.len() == 2).then(|| letters.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn place(country: &str, city: Option<&str>) -> Option<Place> {
+        Some(Place {
+            country: country.to_owned(),
+            city: city.map(str::to_owned),
+        })
+    }
+
+    #[test]
+    fn geocodes_locations() {
+        let cases = [
+            ("SF", place("United States", Some("San Francisco"))),
+            ("Berlin, DE", place("Germany", Some("Berlin"))),
+            ("🌍", None),
+            // Flags and countries on their own
+            ("🇫🇷", place("France", None)),
+            ("Remote 🇩🇪", place("Germany", None)),
+            ("Germany", place("Germany", None)),
+            ("U.S.A.", place("United States", None)),
+            // Cities found within longer text
+            ("Living in Berlin", place("Germany", Some("Berlin"))),
+            ("Paris / Remote", place("France", Some("Paris"))),
+            // Unknown places
+            ("Smalltown, Nowhere", None),
+            ("", None),
+            // Ambiguous names: a trailing code is a region before a country,
+            // and a city in another country is dropped
+            ("MA", place("Morocco", None)),
+            ("Cambridge, MA", place("United States", None)),
+            ("Cambridge, UK", place("United Kingdom", Some("Cambridge"))),
+            ("Georgia", place("Georgia", None)),
+            ("Atlanta, GA", place("United States", Some("Atlanta"))),
+        ];
+
+        for (location, expected) in cases {
+            assert_eq!(geocode(location), expected, "{location:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_flags() {
+        let cases = [
+            ("🇩🇪", Some("DE")),
+            ("Berlin 🇩🇪🇫🇷", Some("DE")),
+            ("🌍", None),
+            ("DE", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(flag_country(text).as_deref(), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn splits_on_punctuation() {
+        assert_eq!(split("Berlin, DE 🌍"), ["berlin", "de"]);
+        assert_eq!(split("  San  Francisco / CA "), ["san francisco", "ca"]);
+        assert_eq!(split("Saint-Denis"), ["saint-denis"]);
+        assert_eq!(split("St. John's"), ["st john's"]);
+    }
+
+    #[test]
+    fn looks_up_country_codes() {
+        assert_eq!(country_code("Germany"), Some("DE"));
+        assert_eq!(country_code("Atlantis"), None);
+    }
+}
@@ -1,57 +1,160 @@
-use anyhow::Result;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::{anyhow, ensure, Result};
 use reqwest::{
-    header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION},
-    Client,
+    header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, IF_NONE_MATCH},
+    Client, Response, StatusCode,
 };
+use tokio::time::sleep;
+
+use crate::config::{GitHubConfig, RetryConfig};
 
 mod types;
 pub use self::types::*;
 
-const GITHUB_URL: &str = "https://api.github.com";
-const USER_AGENT: &str = "Stargazer Gazer";
-
-pub fn create_client(pat: &str) -> Result<Client> {
-    let mut headers = HeaderMap::new();
-    let mut auth: HeaderValue = format!("Bearer {pat}").parse()?;
-    auth.set_sensitive(true);
-    headers.insert(AUTHORIZATION, auth);
-    headers.insert(ACCEPT_ENCODING, "application/json".parse()?);
-    headers.insert(ACCEPT, "application/vnd.github.star+json".parse()?);
-
-    Ok(Client::builder()
-        .default_headers(headers)
-        .user_agent(USER_AGENT)
-        .build()?)
-}
+/// Most items GitHub returns per page of a list endpoint
+const MAX_PAGE_SIZE: u32 = 100;
+/// Longest wait between retries, however many attempts are configured
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
 
-pub async fn get_repository(client: &Client, owner: &str, repo: &str) -> Result<Repository> {
-    let url = format!("{GITHUB_URL}/repos/{owner}/{repo}");
-    let reply = client.get(&url).send().await?;
-    Ok(reply.error_for_status()?.json().await?)
+/// Client for the GitHub REST API
+pub struct GitHub {
+    client: Client,
+    url: String,
+    page_size: u32,
+    retry: RetryConfig,
+    /// Requests sent, including retries
+    requests: AtomicU64,
+    /// Requests left in the rate limit window, as of the last reply, or
+    /// `u64::MAX` before the first
+    rate_remaining: AtomicU64,
 }
 
-// pub async fn get_stargazers(client: &Client, owner: &str, repo: &str) -> Result<Vec<User>> {
-//     let url = format!("{GITHUB_URL}/repos/{owner}/{repo}/stargazers");
-//     let mut users = Vec::new();
+impl GitHub {
+    pub fn new(config: &GitHubConfig, retry: &RetryConfig, token: &str) -> Result<GitHub> {
+        ensure!(
+            (1..=MAX_PAGE_SIZE).contains(&config.page_size),
+            "github.page_size must be between 1 and {MAX_PAGE_SIZE}, not {}",
+            config.page_size
+        );
+
+        let mut headers = HeaderMap::new();
+        let mut auth: HeaderValue = format!("Bearer {token}").parse()?;
+        auth.set_sensitive(true);
+        headers.insert(AUTHORIZATION, auth);
+        headers.insert(ACCEPT_ENCODING, "application/json".parse()?);
+        headers.insert(ACCEPT, "application/vnd.github.star+json".parse()?);
+
+        let client = Client::builder()
+            .default_headers(headers)
+            .user_agent(&config.user_agent)
+            .build()?;
+
+        Ok(GitHub {
+            client,
+            url: config.url.trim_end_matches('/').to_owned(),
+            page_size: config.page_size,
+            retry: retry.clone(),
+            requests: AtomicU64::new(0),
+            rate_remaining: AtomicU64::new(u64::MAX),
+        })
+    }
 
-//     let mut looping = true;
-//     let mut page = 1;
-//     while looping {
-//         let reply = client
-//             .get(&url)
-//             .query(&[("page", page), ("per_page", 100)])
-//             .send()
-//             .await?;
+    pub async fn get_repository(&self, owner: &str, repo: &str) -> Result<Repository> {
+        let url = format!("{}/repos/{owner}/{repo}", self.url);
+        let reply = self.get(&url, &[]).await?;
+        Ok(reply.error_for_status()?.json().await?)
+    }
 
-//         let mut u: Vec<User> = reply.error_for_status()?.json().await?;
+    pub async fn get_organization(&self, login: &str) -> Result<Option<OrganizationProfile>> {
+        let url = format!("{}/orgs/{login}", self.url);
+        let reply = self.get(&url, &[]).await?;
+        if reply.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(reply.error_for_status()?.json().await?))
+    }
 
-//         if u.len() < 100 {
-//             looping = false;
-//         }
+    /// How many requests have been sent so far
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
 
-//         users.append(&mut u);
-//         page += 1;
-//     }
+    /// Requests left before the rate limit resets, once GitHub has said
+    pub fn rate_remaining(&self) -> Option<u64> {
+        match self.rate_remaining.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            remaining => Some(remaining),
+        }
+    }
 
-//     Ok(users)
-// }
+    /// Send a GET request, retrying with exponential backoff when the request
+    /// fails to send, or GitHub replies with a server error or rate limit.
+    /// GitHub signals the primary rate limit with 403 and no requests left,
+    /// and secondary limits with 429.
+    async fn get(&self, url: &str, params: &[(&str, String)]) -> Result<Response> {
+        self.get_if_changed(url, params, None).await
+    }
+
+    /// Send a GET request that GitHub answers with 304 Not Modified if the
+    /// response still has this `etag`. These replies don't count against the
+    /// rate limit.
+    async fn get_if_changed(
+        &self,
+        url: &str,
+        params: &[(&str, String)],
+        etag: Option<&str>,
+    ) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(url).query(params);
+            if let Some(etag) = etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            let reply = request.send().await;
+            if let Ok(reply) = &reply {
+                let remaining = reply
+                    .headers()
+                    .get("x-ratelimit-remaining")
+                    .and_then(|v| v.to_str().ok()?.parse().ok());
+                if let Some(remaining) = remaining {
+                    self.rate_remaining.store(remaining, Ordering::Relaxed);
+                }
+            }
+            let error = match reply {
+                Ok(reply) if !is_retryable(&reply) => return Ok(reply),
+                Ok(reply) => anyhow!("{url} returned {}", reply.status()),
+                Err(e) => e.into(),
+            };
+
+            attempt += 1;
+            if attempt >= self.retry.attempts {
+                return Err(error);
+            }
+
+            let delay = Duration::from_millis(
+                self.retry
+                    .backoff_ms
+                    .saturating_mul(2u64.saturating_pow(attempt - 1)),
+            )
+            .min(MAX_BACKOFF);
+            println!("Retrying in {delay:?}: {error}");
+            sleep(delay).await;
+        }
+    }
+}
+
+fn is_retryable(reply: &Response) -> bool {
+    let status = reply.status();
+    let rate_limited = status == StatusCode::FORBIDDEN
+        && reply
+            .headers()
+            .get("x-ratelimit-remaining")
+            .is_some_and(|v| v.as_bytes() == b"0");
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS || rate_limited
+}
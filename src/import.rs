@@ -0,0 +1,195 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::TryStreamExt;
+use parquet::{
+    file::reader::SerializedFileReader,
+    record::{Field, Row},
+};
+
+use crate::{
+    db::{self, ColumnType, Export, Merge, Storage, Value},
+    export::{Table, TableFormat},
+};
+
+/// Rows collected before each write
+const ROWS_PER_MERGE: usize = 10_000;
+
+/// Merge the users, repos, edges, targets, topics and snapshots of another
+/// database into this one
+pub async fn merge(db_url: &str, other: &str) -> Result<()> {
+    if !other.contains("://") && !Path::new(other).exists() {
+        bail!("{other} not found");
+    }
+
+    let db = db::connect(db_url).await?;
+    let other_db = db::connect_read_only(other).await?;
+    let import = db.add_import(&redact(other)).await?;
+
+    // Users and repos first, so the rows referring to them can be stored
+    for export in [
+        Export::Users,
+        Export::Repos,
+        Export::Edges,
+        Export::Targets,
+        Export::Topics,
+        Export::Snapshots,
+    ] {
+        let count = merge_rows(&*db, import, export, other_db.export(export)).await?;
+        println!("Merged {count} {}", name(export));
+    }
+    Ok(())
+}
+
+/// Merge a table exported by `stargazers export` into this database
+pub async fn import(db_url: &str, table: Table, format: TableFormat, path: &Path) -> Result<()> {
+    let export = match table {
+        Table::Stargazers => bail!("Stargazers can't be imported, import edges instead"),
+        _ => Export::from(table),
+    };
+    let rows =
+        read_rows(export, format, path).with_context(|| format!("Reading {}", path.display()))?;
+
+    let db = db::connect(db_url).await?;
+    let import = db.add_import(&path.display().to_string()).await?;
+    let count = merge_rows(&*db, import, export, futures::stream::iter(rows)).await?;
+    println!("Imported {count} {}", name(export));
+    Ok(())
+}
+
+async fn merge_rows(
+    db: &dyn Storage,
+    import: i64,
+    export: Export,
+    rows: impl futures::Stream<Item = Result<Vec<Value>>>,
+) -> Result<usize> {
+    let mut merge = Merge::new(import);
+    let mut count = 0;
+
+    futures::pin_mut!(rows);
+    while let Some(row) = rows.try_next().await? {
+        count += 1;
+        merge
+            .add(export, row)
+            .with_context(|| format!("Row {count}"))?;
+        if merge.len() >= ROWS_PER_MERGE {
+            db.merge(&merge).await?;
+            merge = Merge::new(import);
+        }
+    }
+    if !merge.is_empty() {
+        db.merge(&merge).await?;
+    }
+    Ok(count)
+}
+
+fn name(export: Export) -> &'static str {
+    match export {
+        Export::Users => "users",
+        Export::Repos => "repos",
+        Export::Targets => "targets",
+        Export::Topics => "topics",
+        Export::Snapshots => "snapshots",
+        _ => "edges",
+    }
+}
+
+/// Leave any password out of a database URL before it's stored
+fn redact(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme), Some(at)) if at > scheme => {
+            format!("{}{}", &url[..scheme + 3], &url[at + 1..])
+        }
+        _ => url.to_owned(),
+    }
+}
+
+type Rows = Box<dyn Iterator<Item = Result<Vec<Value>>>>;
+
+/// Read an exported file row by row, with values in the order of the
+/// export's columns. Columns are matched by name, so extra ones are ignored
+/// and missing ones are empty.
+fn read_rows(export: Export, format: TableFormat, path: &Path) -> Result<Rows> {
+    let columns = export.columns();
+    let file = File::open(path)?;
+
+    Ok(match format {
+        TableFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(file);
+            let headers = reader.headers()?.clone();
+            let indexes: Vec<_> = columns
+                .iter()
+                .map(|(name, _)| headers.iter().position(|header| header == *name))
+                .collect();
+
+            Box::new(reader.into_records().map(move |record| {
+                let record = record?;
+                indexes
+                    .iter()
+                    .zip(columns)
+                    .map(|(i, (name, kind))| match i.and_then(|i| record.get(i)) {
+                        None | Some("") => Ok(Value::Null),
+                        Some(s) => match kind {
+                            ColumnType::Integer => Ok(Value::Integer(
+                                s.parse().with_context(|| format!("Bad {name}: {s}"))?,
+                            )),
+                            ColumnType::Text => Ok(Value::Text(s.to_owned())),
+                        },
+                    })
+                    .collect()
+            }))
+        }
+        TableFormat::Jsonl => Box::new(
+            BufReader::new(file)
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(move |line| {
+                    let object: serde_json::Map<String, serde_json::Value> =
+                        serde_json::from_str(&line?)?;
+                    columns
+                        .iter()
+                        .map(|(name, kind)| match (kind, object.get(*name)) {
+                            (_, None | Some(serde_json::Value::Null)) => Ok(Value::Null),
+                            (ColumnType::Integer, Some(serde_json::Value::Number(n)))
+                                if n.is_i64() =>
+                            {
+                                Ok(Value::Integer(n.as_i64().unwrap_or_default()))
+                            }
+                            (ColumnType::Text, Some(serde_json::Value::String(s))) => {
+                                Ok(Value::Text(s.clone()))
+                            }
+                            (_, Some(value)) => Err(anyhow!("Bad {name}: {value}")),
+                        })
+                        .collect()
+                }),
+        ),
+        TableFormat::Parquet => {
+            let reader = SerializedFileReader::new(file)?;
+            Box::new(reader.into_iter().map(move |row| {
+                let row: Row = row?;
+                columns
+                    .iter()
+                    .map(|(name, kind)| {
+                        let field = row
+                            .get_column_iter()
+                            .find(|(column, _)| column == name)
+                            .map(|(_, field)| field);
+                        match (kind, field) {
+                            (_, None | Some(Field::Null)) => Ok(Value::Null),
+                            (ColumnType::Integer, Some(Field::Long(i))) => Ok(Value::Integer(*i)),
+                            (ColumnType::Integer, Some(Field::Int(i))) => {
+                                Ok(Value::Integer(i64::from(*i)))
+                            }
+                            (ColumnType::Text, Some(Field::Str(s))) => Ok(Value::Text(s.clone())),
+                            (_, Some(field)) => Err(anyhow!("Bad {name}: {field}")),
+                        }
+                    })
+                    .collect()
+            }))
+        }
+    })
+}
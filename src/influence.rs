@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use anyhow::{ensure, Result};
+
+use crate::db;
+
+const MAX_ITERATIONS: usize = 100;
+const TOLERANCE: f64 = 1e-9;
+
+/// Rank every user by PageRank over the follow graph and persist the scores
+pub async fn compute_influence(db_url: &str, damping: f64, weighted: bool) -> Result<()> {
+    // 0 ignores the follow graph entirely, and 1 never converges
+    ensure!(
+        damping > 0.0 && damping < 1.0,
+        "Damping must be between 0 and 1 exclusive, not {damping}"
+    );
+
+    let db = db::connect(db_url).await?;
+
+    let (users, followers): (Vec<i64>, Vec<u64>) = db
+        .get_users()
+        .await?
+        .into_iter()
+        .map(|u| (u.id, u.followers.unwrap_or(0).max(0) as u64))
+        .unzip();
+    let follows: Vec<(i64, i64)> = db
+        .get_user_user_edges()
+        .await?
+        .into_iter()
+        .filter(|e| e.kind == "follower")
+        .map(|e| (e.source, e.target))
+        .collect();
+
+    let scores = pagerank(
+        &users,
+        &follows,
+        damping,
+        weighted.then_some(&followers[..]),
+    );
+    db.set_user_influence(&scores).await?;
+    println!("Computed influence for {} users", scores.len());
+
+    Ok(())
+}
+
+/// Compute PageRank where an edge `(a, b)` means `a` follows `b`.
+///
+/// When each node's follower count on GitHub is given, random jumps favour
+/// users in proportion to it, rather than being spread evenly across all
+/// users. Followers who were never crawled count too.
+fn pagerank(
+    nodes: &[i64],
+    edges: &[(i64, i64)],
+    damping: f64,
+    followers: Option<&[u64]>,
+) -> Vec<(i64, f64)> {
+    let n = nodes.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let index: HashMap<i64, usize> = nodes.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    let edges: Vec<(usize, usize)> = edges
+        .iter()
+        .filter_map(|(a, b)| Some((*index.get(a)?, *index.get(b)?)))
+        .collect();
+
+    let mut out_degree = vec![0usize; n];
+    for (a, _) in &edges {
+        out_degree[*a] += 1;
+    }
+
+    let teleport: Vec<f64> = match followers {
+        Some(followers) => {
+            let total: f64 = followers.iter().map(|f| (f + 1) as f64).sum();
+            followers.iter().map(|f| (f + 1) as f64 / total).collect()
+        }
+        None => vec![1.0 / n as f64; n],
+    };
+
+    let mut rank = teleport.clone();
+    for _ in 0..MAX_ITERATIONS {
+        let dangling: f64 = (0..n)
+            .filter(|i| out_degree[*i] == 0)
+            .map(|i| rank[i])
+            .sum();
+
+        let mut next: Vec<f64> = teleport
+            .iter()
+            .map(|t| (1.0 - damping + damping * dangling) * t)
+            .collect();
+        for (a, b) in &edges {
+            next[*b] += damping * rank[*a] / out_degree[*a] as f64;
+        }
+
+        let delta: f64 = next.iter().zip(&rank).map(|(x, y)| (x - y).abs()).sum();
+        rank = next;
+        if delta < TOLERANCE {
+            break;
+        }
+    }
+
+    nodes.iter().copied().zip(rank).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_scores(scores: &[(i64, f64)], expected: &[(i64, f64)]) {
+        assert_eq!(scores.len(), expected.len());
+        for ((id, score), (expected_id, expected_score)) in scores.iter().zip(expected) {
+            assert_eq!(id, expected_id);
+            assert!(
+                (score - expected_score).abs() < 1e-6,
+                "{id}: {score} != {expected_score}"
+            );
+        }
+    }
+
+    #[test]
+    fn cycle_ranks_evenly() {
+        let scores = pagerank(&[1, 2, 3], &[(1, 2), (2, 3), (3, 1)], 0.85, None);
+        assert_scores(&scores, &[(1, 1.0 / 3.0), (2, 1.0 / 3.0), (3, 1.0 / 3.0)]);
+    }
+
+    #[test]
+    fn followed_user_ranks_highest() {
+        // 1 and 2 follow 3, who follows nobody. Solving by hand, 3 gets 27/47
+        // and the others 10/47 each.
+        let scores = pagerank(&[1, 2, 3], &[(1, 3), (2, 3)], 0.85, None);
+        assert_scores(
+            &scores,
+            &[(1, 10.0 / 47.0), (2, 10.0 / 47.0), (3, 27.0 / 47.0)],
+        );
+    }
+
+    #[test]
+    fn followers_weight_random_jumps() {
+        // Without edges, scores are the teleport weights: followers + 1
+        let scores = pagerank(&[1, 2, 3], &[], 0.85, Some(&[0, 1, 2]));
+        assert_scores(&scores, &[(1, 1.0 / 6.0), (2, 2.0 / 6.0), (3, 3.0 / 6.0)]);
+    }
+}
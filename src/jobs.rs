@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use tokio::{sync::watch, task::AbortHandle};
+use utoipa::ToSchema;
+
+use crate::{
+    config::{Config, CrawlConfig},
+    db::{timestamp, Storage},
+    download::{download_target, Progress},
+    github::GitHub,
+};
+
+/// Downloads started through the API. They are kept in memory, so the list
+/// starts empty each time the server does.
+pub struct Jobs {
+    db: Arc<dyn Storage>,
+    /// Client for the jobs, or why there isn't one
+    github: Result<Arc<GitHub>, String>,
+    crawl: Arc<CrawlConfig>,
+    jobs: Mutex<Vec<Job>>,
+    running: Running,
+}
+
+/// Targets being downloaded, by a job or by `watch`'s sync, so that each is
+/// only downloaded by one of them at a time
+#[derive(Clone, Default)]
+pub struct Running(Arc<Mutex<HashMap<String, Downloader>>>);
+
+/// What is downloading a target
+#[derive(Clone, Copy, Debug)]
+pub enum Downloader {
+    Job(u64),
+    Sync,
+}
+
+/// A target claimed in [`Running`], until this is dropped
+pub struct Claim {
+    running: Running,
+    target: String,
+}
+
+struct Job {
+    state: Arc<watch::Sender<JobState>>,
+    handle: AbortHandle,
+}
+
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct JobState {
+    pub id: u64,
+    /// Repo being downloaded (owner/name)
+    pub target: String,
+    pub status: JobStatus,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub error: Option<String>,
+    pub progress: Progress,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// Why a job couldn't be started
+#[derive(Debug)]
+pub enum StartError {
+    /// The server has no GitHub token
+    NoToken(String),
+    InvalidTarget(String),
+    /// The target is already being downloaded
+    Running(Downloader),
+}
+
+impl Jobs {
+    /// Jobs download into `db` with the configured crawl settings. Without a
+    /// token the jobs can still be listed, but none can be started.
+    pub fn new(config: &Config, token: Result<String>, db: Arc<dyn Storage>) -> Result<Jobs> {
+        let github = match token {
+            Ok(token) => Ok(Arc::new(GitHub::new(
+                &config.github,
+                &config.retry,
+                &token,
+            )?)),
+            Err(e) => Err(e.to_string()),
+        };
+
+        Ok(Jobs {
+            db,
+            github,
+            crawl: Arc::new(config.crawl.clone()),
+            jobs: Mutex::new(Vec::new()),
+            running: Running::default(),
+        })
+    }
+
+    /// Targets being downloaded, to share with anything else that downloads
+    pub fn running(&self) -> Running {
+        self.running.clone()
+    }
+
+    /// Start downloading a repo (owner/name) in the background. With
+    /// `incremental`, only what changed since the last download is fetched.
+    pub fn start(&self, target: &str, incremental: bool) -> Result<JobState, StartError> {
+        let github = self.github.clone().map_err(StartError::NoToken)?;
+        let valid = matches!(
+            target.split_once('/'),
+            Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/')
+        );
+        if !valid {
+            return Err(StartError::InvalidTarget(target.to_owned()));
+        }
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.len() as u64 + 1;
+        let claim = self
+            .running
+            .claim(target, Downloader::Job(id))
+            .map_err(StartError::Running)?;
+
+        let state = JobState {
+            id,
+            target: target.to_owned(),
+            status: JobStatus::Running,
+            started_at: timestamp(&Utc::now()),
+            finished_at: None,
+            error: None,
+            progress: Progress {
+                stage: "repo".to_owned(),
+                ..Default::default()
+            },
+        };
+        let sender = Arc::new(watch::Sender::new(state.clone()));
+
+        let (db, crawl, target, job) = (
+            self.db.clone(),
+            self.crawl.clone(),
+            target.to_owned(),
+            sender.clone(),
+        );
+        let handle = tokio::spawn(async move {
+            // Released when the job finishes or is cancelled
+            let _claim = claim;
+            let report = |progress: &Progress| {
+                job.send_modify(|state| state.progress = progress.clone());
+            };
+            let result =
+                download_target(&*db, &github, &crawl, &target, incremental, &report).await;
+
+            job.send_modify(|state| {
+                state.finished_at = Some(timestamp(&Utc::now()));
+                match result {
+                    Ok(()) => state.status = JobStatus::Succeeded,
+                    Err(e) => {
+                        eprintln!("Job {} failed: {e:#}", state.id);
+                        state.status = JobStatus::Failed;
+                        state.error = Some(format!("{e:#}"));
+                    }
+                }
+            });
+        })
+        .abort_handle();
+
+        jobs.push(Job {
+            state: sender,
+            handle,
+        });
+        Ok(state)
+    }
+
+    /// Stop a running job. Chunks of stargazers already stored are kept,
+    /// and the one being fetched is dropped.
+    pub fn cancel(&self, id: u64) -> Option<JobState> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.iter().find(|job| job.state.borrow().id == id)?;
+
+        job.handle.abort();
+        job.state.send_if_modified(|state| {
+            let running = state.status == JobStatus::Running;
+            if running {
+                state.status = JobStatus::Cancelled;
+                state.finished_at = Some(timestamp(&Utc::now()));
+            }
+            running
+        });
+        let state = job.state.borrow().clone();
+        Some(state)
+    }
+
+    /// Every job, newest first
+    pub fn list(&self) -> Vec<JobState> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter()
+            .rev()
+            .map(|job| job.state.borrow().clone())
+            .collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobState> {
+        self.subscribe(id).map(|state| state.borrow().clone())
+    }
+
+    /// Watch a job's state change as it runs
+    pub fn subscribe(&self, id: u64) -> Option<watch::Receiver<JobState>> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter()
+            .find(|job| job.state.borrow().id == id)
+            .map(|job| job.state.subscribe())
+    }
+}
+
+impl Running {
+    /// Claim `target` for `downloader`, or say what already has it. Targets
+    /// are compared ignoring case, as GitHub does.
+    pub fn claim(&self, target: &str, downloader: Downloader) -> Result<Claim, Downloader> {
+        let key = target.to_lowercase();
+        let mut running = self.0.lock().unwrap();
+        if let Some(other) = running.get(&key) {
+            return Err(*other);
+        }
+
+        running.insert(key.clone(), downloader);
+        Ok(Claim {
+            running: self.clone(),
+            target: key,
+        })
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        self.running.0.lock().unwrap().remove(&self.target);
+    }
+}
+
+impl fmt::Display for Downloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Downloader::Job(id) => write!(f, "job {id}"),
+            Downloader::Sync => write!(f, "the scheduled sync"),
+        }
+    }
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartError::NoToken(reason) => write!(f, "Downloads are disabled: {reason}"),
+            StartError::InvalidTarget(target) => {
+                write!(f, "Invalid repo, expected owner/name: {target}")
+            }
+            StartError::Running(downloader) => {
+                write!(f, "Already being downloaded by {downloader}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StartError {}
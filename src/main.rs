@@ -1,17 +1,60 @@
+use std::{net::IpAddr, path::PathBuf, sync::Arc};
+
 use analyse::analyse;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
 use clap::{Args, Parser, Subcommand};
+use cohort::cohorts;
+use community::compute_communities;
+use company::{add_alias, list_aliases, remove_alias};
+use config::{show_config, Config};
+use db::{Filter, Storage};
 use download::download;
+use export::{export_graph, export_table, GraphFormat, Query, Table, TableFormat};
+use geo::compute_geo;
+use import::{import, merge};
+use influence::compute_influence;
+use jobs::Jobs;
+use recommend::recommend;
+use report::{report, ReportFormat};
+use search::search;
+use top::{timeline, top, OutputFormat, TopList};
+use watch::watch;
 
 mod analyse;
+mod api;
+mod cohort;
+mod community;
+mod company;
+mod config;
 mod db;
 mod download;
+mod export;
+mod geo;
 mod github;
+mod import;
+mod influence;
+mod jobs;
+mod recommend;
+mod report;
+mod search;
+mod top;
+mod watch;
 
 /// Fetch and analyse GitHub stargazers
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None, propagate_version = true)]
 struct Cli {
+    /// Config file, applied over stargazers.toml in the user config and
+    /// current directories
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// SQLite database path or postgres:// URL to read and write
+    /// [default: db/stargazers.sqlite]
+    #[arg(long, global = true)]
+    db: Option<String>,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -23,21 +66,61 @@ enum Command {
 
     /// Analyse data and produce data file for GUI
     Analyse(AnalyseArgs),
+
+    /// Serve the front end and refresh the targets on a schedule
+    Watch(WatchArgs),
+
+    /// Export data for use in other tools
+    Export(ExportArgs),
+
+    /// Merge a table exported by another crawl into this database
+    Import(ImportArgs),
+
+    /// Merge another database's users, repos, edges, targets, topics and
+    /// snapshots into this one
+    Merge(MergeArgs),
+
+    /// Run graph analyses and store the results
+    Compute(ComputeArgs),
+
+    /// Recommend repos based on what our stargazers have starred
+    Recommend(RecommendArgs),
+
+    /// Manage aliases used to group stargazers' companies
+    CompanyAlias(CompanyAliasArgs),
+
+    /// Show how each month's stargazers went on to engage with the repo
+    Cohorts(CohortsArgs),
+
+    /// Write a static HTML or Markdown report on the stargazers
+    Report(ReportArgs),
+
+    /// Print a top list of repos or followed users
+    Top(TopArgs),
+
+    /// Print stars per day on each target repo, with a running total
+    Timeline(TimelineArgs),
+
+    /// Find users and repos by name, description, company or topic
+    Search(SearchArgs),
+
+    /// Inspect the configuration
+    Config(ConfigArgs),
 }
 
 #[derive(Debug, Args)]
 struct DownloadArgs {
-    /// Owner name
-    #[arg(short, long)]
-    owner: String,
+    /// Owner name, defaults to every repo in `targets`
+    #[arg(short, long, requires = "repo")]
+    owner: Option<String>,
 
     /// Repository name
-    #[arg(short, long)]
-    repo: String,
+    #[arg(short, long, requires = "owner")]
+    repo: Option<String>,
 
-    /// Personal access token
+    /// Personal access token, defaults to the configured token source
     #[arg(short, long)]
-    pat: String,
+    pat: Option<String>,
 
     /// Only download new stargazers
     #[arg(short, long, default_value_t = false)]
@@ -49,15 +132,414 @@ struct AnalyseArgs {
     /// Open front end in browser
     #[arg(short, long, default_value_t = false)]
     open: bool,
+
+    /// Serve the front end from this directory (e.g. web/dist) instead of
+    /// the copy built into the binary
+    #[arg(long)]
+    web_dir: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(long)]
+    address: Option<IpAddr>,
+
+    /// Port to listen on
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// Personal access token for downloads started from the front end,
+    /// defaults to the configured token source
+    #[arg(long)]
+    pat: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct WatchArgs {
+    #[command(flatten)]
+    serve: AnalyseArgs,
+
+    /// Hours between refreshes [default: 6]
+    #[arg(short, long)]
+    interval: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct ExportArgs {
+    #[command(subcommand)]
+    command: Option<ExportCommand>,
+
+    #[command(flatten)]
+    table: TableArgs,
+}
+
+#[derive(Debug, Args)]
+struct TableArgs {
+    /// Table to export
+    #[arg(short, long, value_enum, required_unless_present = "query")]
+    table: Option<Table>,
+
+    /// Canned query to export instead of a table
+    #[arg(short, long, value_enum, conflicts_with = "table")]
+    query: Option<Query>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = TableFormat::Csv)]
+    format: TableFormat,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum ExportCommand {
+    /// Export the stargazer social network as a graph
+    Graph(GraphArgs),
+}
+
+#[derive(Debug, Args)]
+struct GraphArgs {
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = GraphFormat::Graphml)]
+    format: GraphFormat,
+
+    /// Output file (defaults to stdout)
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+
+    /// Drop nodes with fewer edges than this
+    #[arg(short, long, default_value_t = 0)]
+    min_degree: usize,
+
+    /// Only include these repositories (owner/name), may be repeated
+    #[arg(short, long)]
+    repo: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct ImportArgs {
+    /// Exported file to read
+    path: PathBuf,
+
+    /// Table the file was exported from: any but stargazers
+    #[arg(short, long, value_enum)]
+    table: Table,
+
+    /// Input format
+    #[arg(short, long, value_enum, default_value_t = TableFormat::Csv)]
+    format: TableFormat,
+}
+
+#[derive(Debug, Args)]
+struct MergeArgs {
+    /// SQLite database path or postgres:// URL to merge in
+    other: String,
+}
+
+#[derive(Debug, Args)]
+struct ComputeArgs {
+    #[command(subcommand)]
+    command: ComputeCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ComputeCommand {
+    /// Rank users by PageRank over the follow graph
+    Influence(InfluenceArgs),
+
+    /// Cluster co-starred repositories into communities
+    Communities(CommunitiesArgs),
+
+    /// Map stored profile locations to countries and cities
+    Geo,
+}
+
+#[derive(Debug, Args)]
+struct InfluenceArgs {
+    /// Probability of following an edge rather than jumping to a random
+    /// user, between 0 and 1 exclusive
+    #[arg(short, long, default_value_t = 0.85)]
+    damping: f64,
+
+    /// Bias random jumps towards users with more followers on GitHub
+    #[arg(short, long, default_value_t = false)]
+    weighted: bool,
+}
+
+#[derive(Debug, Args)]
+struct CommunitiesArgs {
+    /// Ignore repos starred by fewer of our users than this
+    #[arg(short = 's', long, default_value_t = 5)]
+    min_stars: u32,
+
+    /// Discard communities with fewer repos than this
+    #[arg(short = 'z', long, default_value_t = 3)]
+    min_size: usize,
+}
+
+#[derive(Debug, Args)]
+struct RecommendArgs {
+    /// Find repos most often co-starred with this repository (owner/name)
+    #[arg(short, long, required_unless_present = "user", conflicts_with = "user")]
+    repo: Option<String>,
+
+    /// Find repos this user might like
+    #[arg(short, long)]
+    user: Option<String>,
+
+    /// Number of repos to show
+    #[arg(short, long, default_value_t = 20)]
+    limit: u32,
+}
+
+#[derive(Debug, Args)]
+struct CompanyAliasArgs {
+    #[command(subcommand)]
+    command: CompanyAliasCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum CompanyAliasCommand {
+    /// Group a company name under another, e.g. "alphabet" => "Google"
+    Add {
+        /// Company name as written in profiles
+        alias: String,
+
+        /// Name to report it as
+        company: String,
+    },
+
+    /// Remove an alias
+    Remove {
+        /// Company name as written in profiles
+        alias: String,
+    },
+
+    /// List all aliases
+    List,
+}
+
+#[derive(Debug, Args)]
+struct CohortsArgs {
+    /// Repository (owner/name), defaults to all downloaded repos
+    #[arg(short, long)]
+    repo: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct ReportArgs {
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = ReportFormat::Html)]
+    format: ReportFormat,
+
+    /// Directory to write the report and its charts to
+    #[arg(short, long, default_value = "report")]
+    out: PathBuf,
+
+    /// Repository (owner/name), defaults to all downloaded repos
+    #[arg(short, long)]
+    repo: Option<String>,
+
+    /// Number of entries in each top list
+    #[arg(short, long, default_value_t = 10)]
+    limit: u32,
+}
+
+#[derive(Debug, Args)]
+struct TopArgs {
+    /// What to rank
+    #[arg(value_enum)]
+    list: TopList,
+
+    /// Number of entries to show
+    #[arg(short, long, default_value_t = 20)]
+    limit: u32,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct TimelineArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+}
+
+/// Narrows results down to the users who starred a target repo within a
+/// date range, like the API's query parameters
+#[derive(Debug, Args)]
+struct FilterArgs {
+    /// Only count stargazers of this target repo (owner/name)
+    #[arg(short, long)]
+    repo: Option<String>,
+
+    /// Only count users who starred on or after this date or RFC 3339 time
+    #[arg(long, value_parser = db::parse_date)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only count users who starred before this date or RFC 3339 time
+    #[arg(long, value_parser = db::parse_date)]
+    until: Option<DateTime<Utc>>,
+}
+
+impl From<FilterArgs> for Filter {
+    fn from(args: FilterArgs) -> Filter {
+        Filter {
+            repo: args.repo,
+            since: args.since,
+            until: args.until,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct SearchArgs {
+    /// Words to search for, matched as prefixes
+    query: String,
+
+    /// Number of users and repos to show
+    #[arg(short, long, default_value_t = 20)]
+    limit: u32,
+}
+
+#[derive(Debug, Args)]
+struct ConfigArgs {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Print the effective config after applying files, env vars and flags
+    Show,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    // CLI flags take precedence over config files and env vars
+    let mut figment = Config::figment(cli.config.as_deref())?;
+    if let Some(db) = &cli.db {
+        figment = figment.merge(("db", db));
+    }
+    match &cli.command {
+        Command::Download(args) if args.quick => figment = figment.merge(("crawl.quick", true)),
+        Command::Analyse(args) | Command::Watch(WatchArgs { serve: args, .. }) => {
+            if let Some(address) = args.address {
+                figment = figment.merge(("server.address", address));
+            }
+            if let Some(port) = args.port {
+                figment = figment.merge(("server.port", port));
+            }
+        }
+        _ => (),
+    }
+    if let Command::Watch(WatchArgs {
+        interval: Some(hours),
+        ..
+    }) = &cli.command
+    {
+        figment = figment.merge(("sync.interval_hours", hours));
+    }
+    let config: Config = figment.extract()?;
+    config.sync.interval()?;
+
+    let db = config.db.as_str();
+
     match cli.command {
-        Command::Download(args) => download(&args.pat, &args.owner, &args.repo, args.quick).await?,
-        Command::Analyse(args) => analyse(args.open).await?,
+        Command::Download(args) => {
+            let targets = match (args.owner, args.repo) {
+                (Some(owner), Some(repo)) => vec![format!("{owner}/{repo}")],
+                _ => config.targets.clone(),
+            };
+            if targets.is_empty() {
+                bail!("Nothing to download: pass --owner and --repo, or configure targets");
+            }
+
+            let token = match args.pat {
+                Some(pat) => pat,
+                None => config.token()?,
+            };
+            download(&config, &token, &targets).await?
+        }
+        Command::Analyse(args) => {
+            let token = match args.pat {
+                Some(pat) => Ok(pat),
+                None => config.token(),
+            };
+            let db: Arc<dyn Storage> = db::connect(db).await?.into();
+            let jobs = Jobs::new(&config, token, db.clone())?;
+            analyse(db, &config.server, jobs, args.open, args.web_dir.as_deref()).await?
+        }
+        Command::Watch(args) => {
+            let serve = args.serve;
+            let token = match serve.pat {
+                Some(pat) => pat,
+                None => config.token()?,
+            };
+            watch(config, &token, serve.open, serve.web_dir.as_deref()).await?
+        }
+        Command::Export(args) => match args.command {
+            Some(ExportCommand::Graph(args)) => {
+                export_graph(
+                    db,
+                    args.format,
+                    args.out.as_deref(),
+                    args.min_degree,
+                    &args.repo,
+                )
+                .await?
+            }
+            None => {
+                let args = args.table;
+                let export = match (args.table, args.query) {
+                    (Some(table), _) => table.into(),
+                    (None, Some(query)) => query.into(),
+                    (None, None) => bail!("Either --table or --query is required"),
+                };
+                export_table(db, export, args.format, args.out.as_deref()).await?
+            }
+        },
+        Command::Import(args) => import(db, args.table, args.format, &args.path).await?,
+        Command::Merge(args) => merge(db, &args.other).await?,
+        Command::Compute(args) => match args.command {
+            ComputeCommand::Influence(args) => {
+                compute_influence(db, args.damping, args.weighted).await?
+            }
+            ComputeCommand::Communities(args) => {
+                compute_communities(db, args.min_stars, args.min_size).await?
+            }
+            ComputeCommand::Geo => compute_geo(db).await?,
+        },
+        Command::Recommend(args) => {
+            recommend(db, args.repo.as_deref(), args.user.as_deref(), args.limit).await?
+        }
+        Command::CompanyAlias(args) => match args.command {
+            CompanyAliasCommand::Add { alias, company } => add_alias(db, &alias, &company).await?,
+            CompanyAliasCommand::Remove { alias } => remove_alias(db, &alias).await?,
+            CompanyAliasCommand::List => list_aliases(db).await?,
+        },
+        Command::Cohorts(args) => cohorts(db, args.repo.as_deref()).await?,
+        Command::Report(args) => {
+            report(db, args.format, &args.out, args.repo.as_deref(), args.limit).await?
+        }
+        Command::Top(args) => {
+            top(db, args.list, &args.filter.into(), args.limit, args.format).await?
+        }
+        Command::Timeline(args) => timeline(db, &args.filter.into(), args.format).await?,
+        Command::Search(args) => search(db, &args.query, args.limit).await?,
+        Command::Config(args) => match args.command {
+            ConfigCommand::Show => show_config(&config, cli.config.as_deref())?,
+        },
     }
 
     Ok(())
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::db::{self, NotFound, Page, RepoScore, Storage};
+
+/// Repos most often starred alongside `full_name`, ranked by the cosine
+/// similarity of their stargazer sets
+pub async fn similar_repos(
+    db: &dyn Storage,
+    full_name: &str,
+    page: Page,
+) -> Result<Vec<RepoScore>> {
+    let repo = db
+        .get_repo_id(full_name)
+        .await?
+        .ok_or_else(|| NotFound(format!("Repository {full_name}")))?;
+
+    let stars: HashMap<i64, u32> = db.get_star_counts().await?.into_iter().collect();
+    let own = stars.get(&repo).copied().unwrap_or(0);
+
+    // A download running alongside can star repos between the two queries,
+    // so counts may be missing
+    let scores = db
+        .get_costarred_for_repo(repo)
+        .await?
+        .into_iter()
+        .filter_map(|(other, common)| Some((other, cosine(common, own, *stars.get(&other)?))))
+        .collect();
+
+    top_named(db, scores, page).await
+}
+
+/// Repos that `username` hasn't starred, scored by their similarity to every
+/// repo they have starred
+pub async fn recommend_repos(
+    db: &dyn Storage,
+    username: &str,
+    page: Page,
+) -> Result<Vec<RepoScore>> {
+    let user = db
+        .get_user_id(username)
+        .await?
+        .ok_or_else(|| NotFound(format!("User {username}")))?;
+
+    let stars: HashMap<i64, u32> = db.get_star_counts().await?.into_iter().collect();
+
+    let mut scores: HashMap<i64, f64> = HashMap::new();
+    for (source, candidate, common) in db.get_costarred_for_user(user).await? {
+        // Skip repos starred since the counts were read, as in `similar_repos`
+        let (Some(a), Some(b)) = (stars.get(&source), stars.get(&candidate)) else {
+            continue;
+        };
+        *scores.entry(candidate).or_default() += cosine(common, *a, *b);
+    }
+
+    top_named(db, scores.into_iter().collect(), page).await
+}
+
+fn cosine(common: u32, a: u32, b: u32) -> f64 {
+    if a == 0 || b == 0 {
+        return 0.0;
+    }
+    common as f64 / (a as f64 * b as f64).sqrt()
+}
+
+async fn top_named(
+    db: &dyn Storage,
+    mut scores: Vec<(i64, f64)>,
+    page: Page,
+) -> Result<Vec<RepoScore>> {
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    let scores = page.slice(scores);
+
+    let ids: Vec<i64> = scores.iter().map(|(id, _)| *id).collect();
+    let mut names = db.get_repo_names(&ids).await?;
+
+    Ok(scores
+        .into_iter()
+        .filter_map(|(id, score)| {
+            Some(RepoScore {
+                full_name: names.remove(&id)?,
+                score,
+            })
+        })
+        .collect())
+}
+
+pub async fn recommend(
+    db_url: &str,
+    repo: Option<&str>,
+    user: Option<&str>,
+    limit: u32,
+) -> Result<()> {
+    let db = db::connect(db_url).await?;
+    let page = Page { limit, offset: 0 };
+
+    let results = match (repo, user) {
+        (Some(repo), _) => similar_repos(&*db, repo, page).await?,
+        (None, Some(user)) => recommend_repos(&*db, user, page).await?,
+        (None, None) => return Err(anyhow!("Either a repository or a user is required")),
+    };
+
+    for r in results {
+        println!("{:.4}  {}", r.score, r.full_name);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use std::fmt::Write;
+
+use chrono::NaiveDate;
+
+use crate::export::escape_xml;
+
+/// Line colours, repeated if there are more series
+const COLOURS: &[&str] = &[
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1",
+];
+
+const WIDTH: f64 = 720.0;
+
+/// A named line of values over time
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(NaiveDate, u32)>,
+}
+
+/// Draw each series as a line over a shared date axis
+pub fn line_chart(series: &[Series]) -> String {
+    let (left, right, top, bottom) = (50.0, 20.0, 20.0, 30.0);
+    let height = 260.0;
+    let (plot_width, plot_height) = (WIDTH - left - right, height - top - bottom);
+
+    let days = series
+        .iter()
+        .flat_map(|s| s.points.iter().map(|(day, _)| *day));
+    let (Some(first), Some(last)) = (days.clone().min(), days.max()) else {
+        return empty(height);
+    };
+    let max = series
+        .iter()
+        .flat_map(|s| s.points.iter().map(|(_, value)| *value))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let span = (last - first).num_days().max(1) as f64;
+    let x = |day: NaiveDate| left + (day - first).num_days() as f64 / span * plot_width;
+    let y = |value: u32| top + plot_height - f64::from(value) / f64::from(max) * plot_height;
+
+    let mut svg = open(height);
+    let mut ticks: Vec<u32> = (0..=4).map(|i| max * i / 4).collect();
+    ticks.dedup();
+    for value in ticks {
+        let _ = write!(
+            svg,
+            r##"<line x1="{left}" x2="{}" y1="{y:.1}" y2="{y:.1}" stroke="#ddd"/><text x="{}" y="{:.1}" text-anchor="end">{value}</text>"##,
+            WIDTH - right,
+            left - 6.0,
+            y(value) + 4.0,
+            y = y(value),
+        );
+    }
+    let _ = write!(
+        svg,
+        r#"<text x="{left}" y="{}">{first}</text><text x="{}" y="{}" text-anchor="end">{last}</text>"#,
+        height - 8.0,
+        WIDTH - right,
+        height - 8.0,
+    );
+
+    for (i, s) in series.iter().enumerate() {
+        let colour = COLOURS[i % COLOURS.len()];
+        let points: Vec<String> = s
+            .points
+            .iter()
+            .map(|(day, value)| format!("{:.1},{:.1}", x(*day), y(*value)))
+            .collect();
+        let _ = write!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{colour}" stroke-width="2"/>"#,
+            points.join(" ")
+        );
+
+        // Legend in the top left corner, where running totals start low
+        let legend = top + 8.0 + 16.0 * i as f64;
+        let _ = write!(
+            svg,
+            r#"<rect x="{}" y="{}" width="10" height="10" fill="{colour}"/><text x="{}" y="{}">{}</text>"#,
+            left + 10.0,
+            legend - 9.0,
+            left + 26.0,
+            legend,
+            escape_xml(&s.name)
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Draw a horizontal bar for each label, longest first as given
+pub fn bar_chart(bars: &[(String, u32)]) -> String {
+    let (label_width, row_height) = (220.0, 22.0);
+    let bar_width = WIDTH - label_width - 60.0;
+    let height = row_height * bars.len() as f64 + 10.0;
+
+    if bars.is_empty() {
+        return empty(60.0);
+    }
+    let max = bars
+        .iter()
+        .map(|(_, value)| *value)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut svg = open(height);
+    for (i, (label, value)) in bars.iter().enumerate() {
+        let y = 5.0 + row_height * i as f64;
+        let width = f64::from(*value) / f64::from(max) * bar_width;
+        let short = match label.char_indices().nth(32) {
+            Some((end, _)) => format!("{}…", &label[..end]),
+            None => label.clone(),
+        };
+        let _ = write!(
+            svg,
+            r##"<text x="{}" y="{}" text-anchor="end">{}</text><rect x="{label_width}" y="{}" width="{width:.1}" height="{}" fill="#4e79a7"><title>{}: {value}</title></rect><text x="{:.1}" y="{}">{value}</text>"##,
+            label_width - 8.0,
+            y + 15.0,
+            escape_xml(&short),
+            y + 3.0,
+            row_height - 6.0,
+            escape_xml(label),
+            label_width + width + 6.0,
+            y + 15.0,
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+fn open(height: f64) -> String {
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {WIDTH} {height}" width="{WIDTH}" height="{height}" font-family="sans-serif" font-size="12">"#
+    )
+}
+
+fn empty(height: f64) -> String {
+    format!(
+        r##"{}<text x="{}" y="{}" text-anchor="middle" fill="#888">No data</text></svg>"##,
+        open(height),
+        WIDTH / 2.0,
+        height / 2.0
+    )
+}
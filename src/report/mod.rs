@@ -0,0 +1,327 @@
+use std::{collections::HashSet, fmt::Write as _, fs, path::Path};
+
+use anyhow::Result;
+use chrono::{Days, NaiveDate, Utc};
+use clap::ValueEnum;
+use futures::TryStreamExt;
+
+use self::chart::{bar_chart, line_chart, Series};
+use crate::{
+    company::top_companies,
+    db::{self, Export, Filter, Page, Storage, Value},
+    export::escape_xml,
+};
+
+mod chart;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ReportFormat {
+    Html,
+    Md,
+}
+
+/// Everything shown in a report, gathered with the queries behind the API
+struct Report {
+    title: String,
+    generated_at: String,
+    summary: Vec<(&'static str, String)>,
+    timeline: Vec<Series>,
+    rankings: Vec<Ranking>,
+}
+
+/// A top list, drawn as a bar chart and a table
+struct Ranking {
+    title: &'static str,
+    /// File name of the chart in Markdown reports
+    slug: &'static str,
+    description: &'static str,
+    column: &'static str,
+    rows: Vec<(String, u32)>,
+}
+
+/// Write a self-contained report on the stargazers of `repo`, or of every
+/// target repo, into the `out` directory
+pub async fn report(
+    db_url: &str,
+    format: ReportFormat,
+    out: &Path,
+    repo: Option<&str>,
+    limit: u32,
+) -> Result<()> {
+    let db = db::connect(db_url).await?;
+    let report = gather(&*db, repo, limit).await?;
+
+    fs::create_dir_all(out)?;
+    let path = match format {
+        ReportFormat::Html => {
+            let path = out.join("index.html");
+            fs::write(&path, html(&report))?;
+            path
+        }
+        ReportFormat::Md => {
+            // Markdown viewers don't render inline SVG, so charts are files
+            // next to the report
+            fs::write(out.join("timeline.svg"), line_chart(&report.timeline))?;
+            for ranking in &report.rankings {
+                fs::write(
+                    out.join(format!("{}.svg", ranking.slug)),
+                    bar_chart(&ranking.rows),
+                )?;
+            }
+            let path = out.join("report.md");
+            fs::write(&path, markdown(&report))?;
+            path
+        }
+    };
+
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+async fn gather(db: &dyn Storage, repo: Option<&str>, limit: u32) -> Result<Report> {
+    let filter = Filter {
+        repo: repo.map(str::to_owned),
+        ..Default::default()
+    };
+    let page = Page { limit, offset: 0 };
+
+    let targets: HashSet<String> = db
+        .get_repo_names(&db.get_targets().await?)
+        .await?
+        .into_values()
+        .collect();
+
+    // Running star totals of each target repo
+    let mut timeline: Vec<Series> = Vec::new();
+    let mut stars = 0;
+    let mut recent = 0;
+    let month_ago = Utc::now().date_naive() - Days::new(30);
+    let mut rows = db.export(Export::StarTimeline);
+    while let Some(row) = rows.try_next().await? {
+        let [Value::Text(name), Value::Text(day), Value::Integer(count), Value::Integer(total)] =
+            &row[..]
+        else {
+            continue;
+        };
+        if repo.is_some_and(|repo| repo != name) {
+            continue;
+        }
+        let Ok(day) = NaiveDate::parse_from_str(day, "%Y-%m-%d") else {
+            continue;
+        };
+
+        stars += count;
+        if day >= month_ago {
+            recent += count;
+        }
+        if timeline.last().is_none_or(|s| &s.name != name) {
+            timeline.push(Series {
+                name: name.clone(),
+                points: Vec::new(),
+            });
+        }
+        if let Some(series) = timeline.last_mut() {
+            series.points.push((day, u32::try_from(*total)?));
+        }
+    }
+    let days = timeline
+        .iter()
+        .flat_map(|s| s.points.iter().map(|(day, _)| *day));
+    let first = days.clone().min();
+    let last = days.max();
+
+    // Targets are starred by every stargazer, so would top the list
+    let mut costarred: Vec<_> = db
+        .get_top_repos(
+            "stargazer",
+            &filter,
+            Page {
+                limit: limit.saturating_add(targets.len() as u32),
+                offset: 0,
+            },
+        )
+        .await?
+        .into_iter()
+        .filter(|r| !targets.contains(&r.full_name))
+        .map(|r| (r.full_name, r.count))
+        .collect();
+    costarred.truncate(limit as usize);
+
+    let rankings = vec![
+        Ranking {
+            title: "Top co-starred repos",
+            slug: "costarred",
+            description: "Other repos starred by the most stargazers",
+            column: "Repo",
+            rows: costarred,
+        },
+        Ranking {
+            title: "Top followed users",
+            slug: "following",
+            description: "Users followed by the most stargazers",
+            column: "User",
+            rows: db
+                .get_top_following(&filter, page)
+                .await?
+                .into_iter()
+                .map(|u| (u.login, u.count))
+                .collect(),
+        },
+        Ranking {
+            title: "Top subscribed repos",
+            slug: "subscribed",
+            description: "Repos watched by the most stargazers",
+            column: "Repo",
+            rows: db
+                .get_top_repos("subscriber", &filter, page)
+                .await?
+                .into_iter()
+                .map(|r| (r.full_name, r.count))
+                .collect(),
+        },
+        Ranking {
+            title: "Top companies",
+            slug: "companies",
+            description: "Where stargazers work, grouped by company alias",
+            column: "Company",
+            rows: top_companies(db, &filter, page)
+                .await?
+                .into_iter()
+                .map(|c| (c.company, c.count))
+                .collect(),
+        },
+        Ranking {
+            title: "Top locations",
+            slug: "locations",
+            description: "Countries stargazers live in, from their profile location",
+            column: "Country",
+            rows: db
+                .get_stargazer_countries(&filter, page)
+                .await?
+                .into_iter()
+                .map(|c| (c.country, c.count))
+                .collect(),
+        },
+    ];
+
+    let mut target_names: Vec<_> = targets.into_iter().collect();
+    target_names.sort();
+    let summary = vec![
+        (
+            "Target repos",
+            match repo {
+                Some(repo) => repo.to_owned(),
+                None => target_names.join(", "),
+            },
+        ),
+        ("Stars", stars.to_string()),
+        ("Stars in the last 30 days", recent.to_string()),
+        (
+            "First star",
+            first.map_or_else(|| "-".to_owned(), |day| day.to_string()),
+        ),
+        (
+            "Latest star",
+            last.map_or_else(|| "-".to_owned(), |day| day.to_string()),
+        ),
+        ("Users stored", db.get_users().await?.len().to_string()),
+        ("Repos stored", db.get_repos().await?.len().to_string()),
+    ];
+
+    Ok(Report {
+        title: format!("Stargazers of {}", repo.unwrap_or("our repos")),
+        generated_at: db::timestamp(&Utc::now()),
+        summary,
+        timeline,
+        rankings,
+    })
+}
+
+fn html(report: &Report) -> String {
+    let mut html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 760px; margin: 2em auto; color: #222; }}
+table {{ border-collapse: collapse; margin: 1em 0; }}
+th, td {{ padding: 4px 12px; border-bottom: 1px solid #ddd; text-align: left; }}
+td.count {{ text-align: right; }}
+.muted {{ color: #777; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p class="muted">Generated {}</p>
+<table>
+"#,
+        escape_xml(&report.generated_at),
+        title = escape_xml(&report.title),
+    );
+    for (label, value) in &report.summary {
+        let _ = writeln!(
+            html,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape_xml(label),
+            escape_xml(value)
+        );
+    }
+    html.push_str("</table>\n<h2>Star timeline</h2>\n");
+    html.push_str(&line_chart(&report.timeline));
+    html.push('\n');
+
+    for ranking in &report.rankings {
+        let _ = write!(
+            html,
+            "<h2>{}</h2>\n<p>{}</p>\n{}\n<table>\n<tr><th>{}</th><th>Stargazers</th></tr>\n",
+            escape_xml(ranking.title),
+            escape_xml(ranking.description),
+            bar_chart(&ranking.rows),
+            escape_xml(ranking.column),
+        );
+        for (label, count) in &ranking.rows {
+            let _ = writeln!(
+                html,
+                r#"<tr><td>{}</td><td class="count">{count}</td></tr>"#,
+                escape_xml(label)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn markdown(report: &Report) -> String {
+    let mut md = format!(
+        "# {}\n\nGenerated {}\n\n| | |\n| --- | --- |\n",
+        report.title, report.generated_at
+    );
+    for (label, value) in &report.summary {
+        let _ = writeln!(md, "| {label} | {} |", escape_cell(value));
+    }
+    md.push_str("\n## Star timeline\n\n![Star timeline](timeline.svg)\n");
+
+    for ranking in &report.rankings {
+        let _ = write!(
+            md,
+            "\n## {title}\n\n{}\n\n![{title}]({}.svg)\n\n| {} | Stargazers |\n| --- | ---: |\n",
+            ranking.description,
+            ranking.slug,
+            ranking.column,
+            title = ranking.title,
+        );
+        for (label, count) in &ranking.rows {
+            let _ = writeln!(md, "| {} | {count} |", escape_cell(label));
+        }
+    }
+    md
+}
+
+/// Stop text from breaking out of a Markdown table cell
+fn escape_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
@@ -0,0 +1,45 @@
+use anyhow::{bail, Result};
+
+use crate::db::{self, Page};
+
+/// Split free text into the words to search for. Each backend matches every
+/// word as a prefix.
+pub fn words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| word.replace('"', ""))
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+pub async fn search(db_url: &str, text: &str, limit: u32) -> Result<()> {
+    let words = words(text);
+    if words.is_empty() {
+        bail!("Nothing to search for");
+    }
+
+    let db = db::connect(db_url).await?;
+    let page = Page { limit, offset: 0 };
+
+    println!("Users:");
+    for u in db.search_users(&words, page).await? {
+        let details: Vec<&str> = [u.name.as_deref(), u.company.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        if details.is_empty() {
+            println!("  {}", u.login);
+        } else {
+            println!("  {} ({})", u.login, details.join(", "));
+        }
+    }
+
+    println!("Repos:");
+    for r in db.search_repos(&words, page).await? {
+        match r.description {
+            Some(description) => println!("  {}: {description}", r.full_name),
+            None => println!("  {}", r.full_name),
+        }
+    }
+
+    Ok(())
+}
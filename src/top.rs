@@ -0,0 +1,190 @@
+use std::io::{self, Write};
+
+use anyhow::Result;
+use chrono::Duration;
+use clap::ValueEnum;
+use futures::TryStreamExt;
+use serde::{
+    ser::{SerializeMap, SerializeSeq},
+    Serialize, Serializer,
+};
+
+use crate::db::{self, Export, Filter, Page, Value};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TopList {
+    /// Repos starred by the most stargazers
+    Repos,
+    /// Users followed by the most stargazers
+    Following,
+    /// Repos watched by the most stargazers
+    Subscribed,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Print a top list, ranked with the same queries as the API
+pub async fn top(
+    db_url: &str,
+    list: TopList,
+    filter: &Filter,
+    limit: u32,
+    format: OutputFormat,
+) -> Result<()> {
+    let db = db::connect(db_url).await?;
+    let page = Page { limit, offset: 0 };
+
+    let (columns, rows): (&[&str], Vec<Vec<Value>>) = match list {
+        TopList::Repos | TopList::Subscribed => {
+            let kind = match list {
+                TopList::Repos => "stargazer",
+                _ => "subscriber",
+            };
+            let repos = db.get_top_repos(kind, filter, page).await?;
+            (
+                &["full_name", "count"],
+                repos
+                    .into_iter()
+                    .map(|r| vec![Value::Text(r.full_name), Value::Integer(r.count.into())])
+                    .collect(),
+            )
+        }
+        TopList::Following => {
+            let users = db.get_top_following(filter, page).await?;
+            (
+                &["login", "count"],
+                users
+                    .into_iter()
+                    .map(|u| vec![Value::Text(u.login), Value::Integer(u.count.into())])
+                    .collect(),
+            )
+        }
+    };
+
+    print(columns, &rows, format)
+}
+
+/// Print stars per day on each target repo, with a running total
+pub async fn timeline(db_url: &str, filter: &Filter, format: OutputFormat) -> Result<()> {
+    let db = db::connect(db_url).await?;
+
+    // Filter the days rather than the stars, so totals still count every
+    // earlier star. A day is kept if any part of it is within the range.
+    let rows: Vec<Vec<Value>> = db
+        .export(Export::StarTimeline)
+        .try_filter(|row| {
+            let keep = match &row[..] {
+                [Value::Text(name), Value::Text(day), ..] => match db::parse_date(day) {
+                    Ok(start) => {
+                        let end = start + Duration::days(1);
+                        filter.repo.as_ref().is_none_or(|repo| repo == name)
+                            && filter.since.is_none_or(|since| end > since)
+                            && filter.until.is_none_or(|until| start < until)
+                    }
+                    Err(_) => false,
+                },
+                _ => false,
+            };
+            futures::future::ready(keep)
+        })
+        .try_collect()
+        .await?;
+
+    let columns: Vec<&str> = Export::StarTimeline
+        .columns()
+        .iter()
+        .map(|(name, _)| *name)
+        .collect();
+    print(&columns, &rows, format)
+}
+
+fn print(columns: &[&str], rows: &[Vec<Value>], format: OutputFormat) -> Result<()> {
+    let mut out = io::stdout().lock();
+
+    match format {
+        OutputFormat::Table => {
+            let cells: Vec<Vec<String>> = rows
+                .iter()
+                .map(|row| row.iter().map(cell).collect())
+                .collect();
+            let widths: Vec<usize> = columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| {
+                    cells
+                        .iter()
+                        .map(|row| row[i].chars().count())
+                        .chain([column.len()])
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect();
+            // Numbers are right aligned, going by the first row
+            let numeric: Vec<bool> = (0..columns.len())
+                .map(|i| matches!(rows.first().map(|row| &row[i]), Some(Value::Integer(_))))
+                .collect();
+
+            let line = |values: &mut dyn Iterator<Item = &str>| {
+                values
+                    .zip(widths.iter().zip(&numeric))
+                    .map(|(value, (width, numeric))| match numeric {
+                        true => format!("{value:>width$}"),
+                        false => format!("{value:<width$}"),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("  ")
+                    .trim_end()
+                    .to_owned()
+            };
+            writeln!(out, "{}", line(&mut columns.iter().copied()))?;
+            for row in &cells {
+                writeln!(out, "{}", line(&mut row.iter().map(String::as_str)))?;
+            }
+        }
+        OutputFormat::Json => {
+            let mut json = serde_json::Serializer::new(&mut out);
+            let mut seq = json.serialize_seq(Some(rows.len()))?;
+            for row in rows {
+                seq.serialize_element(&Object(columns, row))?;
+            }
+            SerializeSeq::end(seq)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut out);
+            csv.write_record(columns)?;
+            for row in rows {
+                csv.write_record(row.iter().map(cell))?;
+            }
+            csv.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(i) => i.to_string(),
+        Value::Text(s) => s.clone(),
+    }
+}
+
+/// A row as a JSON object, keeping the columns in order
+struct Object<'a>(&'a [&'a str], &'a [Value]);
+
+impl Serialize for Object<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (column, value) in self.0.iter().zip(self.1) {
+            map.serialize_entry(column, value)?;
+        }
+        map.end()
+    }
+}
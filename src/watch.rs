@@ -0,0 +1,100 @@
+use std::{path::Path, sync::Arc, time::Instant};
+
+use anyhow::Result;
+
+use crate::{
+    analyse::analyse,
+    config::{Config, CrawlConfig},
+    db::{self, Storage},
+    download::download_target,
+    github::GitHub,
+    jobs::{Downloader, Jobs, Running},
+};
+
+/// Serve the API and front end, refreshing the targets in the background
+/// every `sync.interval_hours`
+pub async fn watch(config: Config, token: &str, open: bool, web_dir: Option<&Path>) -> Result<()> {
+    let db: Arc<dyn Storage> = db::connect(&config.db).await?.into();
+    db.interrupt_sync_runs().await?;
+    let github = GitHub::new(&config.github, &config.retry, token)?;
+    let jobs = Jobs::new(&config, Ok(token.to_owned()), db.clone())?;
+    let running = jobs.running();
+    let interval = config.sync.interval()?;
+    let hours = config.sync.interval_hours;
+
+    let Config {
+        targets,
+        crawl,
+        server,
+        ..
+    } = config;
+    // The first sync starts straight away, alongside the server rather than
+    // after it's up, so a long one doesn't delay serving what's stored
+    println!("Syncing targets now, then every {hours} hours");
+    let sync_db = db.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = sync(&*sync_db, &github, &crawl, &targets, &running).await {
+                eprintln!("Sync failed: {e:#}");
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+
+    analyse(db, &server, jobs, open, web_dir).await
+}
+
+/// Incrementally download each target, recording the run in `sync_run`. A
+/// target that fails doesn't stop the others, and one that a job is already
+/// downloading is skipped.
+async fn sync(
+    db: &dyn Storage,
+    github: &GitHub,
+    crawl: &CrawlConfig,
+    targets: &[String],
+    running: &Running,
+) -> Result<()> {
+    let id = db.start_sync_run().await?;
+    let started = Instant::now();
+    let requests = github.requests();
+
+    // Without configured targets, refresh the ones already downloaded
+    let targets = match targets.is_empty() {
+        true => db
+            .get_repo_names(&db.get_targets().await?)
+            .await?
+            .into_values()
+            .collect(),
+        false => targets.to_vec(),
+    };
+
+    let mut errors = Vec::new();
+    for target in &targets {
+        let _claim = match running.claim(target, Downloader::Sync) {
+            Ok(claim) => claim,
+            Err(other) => {
+                println!("Skipping {target}: already being downloaded by {other}");
+                continue;
+            }
+        };
+        if let Err(e) = download_target(db, github, crawl, target, true, &|_| ()).await {
+            errors.push(format!("{target}: {e:#}"));
+        }
+    }
+
+    let error = errors.join("\n");
+    let (status, error) = match errors.is_empty() {
+        true => ("succeeded", None),
+        false => ("failed", Some(error.as_str())),
+    };
+    db.finish_sync_run(
+        id,
+        status,
+        started.elapsed().as_millis() as i64,
+        (github.requests() - requests) as i64,
+        error,
+    )
+    .await?;
+    println!("Sync {status} after {:.1?}", started.elapsed());
+    Ok(())
+}
@@ -0,0 +1,115 @@
+// ISO 3166-1 numeric codes, which world-atlas uses as feature ids, of the
+// alpha-2 codes in the gazetteer (data/gazetteer.tsv)
+export const numericCodes: Record<string, string> = {
+  AE: "784",
+  AM: "051",
+  AR: "032",
+  AT: "040",
+  AU: "036",
+  AZ: "031",
+  BA: "070",
+  BD: "050",
+  BE: "056",
+  BG: "100",
+  BO: "068",
+  BR: "076",
+  BY: "112",
+  CA: "124",
+  CH: "756",
+  CL: "152",
+  CM: "120",
+  CN: "156",
+  CO: "170",
+  CR: "188",
+  CU: "192",
+  CY: "196",
+  CZ: "203",
+  DE: "276",
+  DK: "208",
+  DO: "214",
+  EC: "218",
+  EE: "233",
+  EG: "818",
+  ES: "724",
+  ET: "231",
+  FI: "246",
+  FR: "250",
+  GB: "826",
+  GE: "268",
+  GH: "288",
+  GR: "300",
+  GT: "320",
+  HK: "344",
+  HN: "340",
+  HR: "191",
+  HU: "348",
+  ID: "360",
+  IE: "372",
+  IL: "376",
+  IN: "356",
+  IQ: "368",
+  IR: "364",
+  IS: "352",
+  IT: "380",
+  JO: "400",
+  JP: "392",
+  KE: "404",
+  KG: "417",
+  KH: "116",
+  KR: "410",
+  KZ: "398",
+  LB: "422",
+  LK: "144",
+  LT: "440",
+  LU: "442",
+  LV: "428",
+  MA: "504",
+  MD: "498",
+  ME: "499",
+  MK: "807",
+  MM: "104",
+  MN: "496",
+  MT: "470",
+  MX: "484",
+  MY: "458",
+  NG: "566",
+  NI: "558",
+  NL: "528",
+  NO: "578",
+  NP: "524",
+  NZ: "554",
+  PA: "591",
+  PE: "604",
+  PH: "608",
+  PK: "586",
+  PL: "616",
+  PR: "630",
+  PT: "620",
+  PY: "600",
+  QA: "634",
+  RO: "642",
+  RS: "688",
+  RU: "643",
+  RW: "646",
+  SA: "682",
+  SE: "752",
+  SG: "702",
+  SI: "705",
+  SK: "703",
+  SN: "686",
+  SV: "222",
+  TH: "764",
+  TN: "788",
+  TR: "792",
+  TW: "158",
+  TZ: "834",
+  UA: "804",
+  UG: "800",
+  US: "840",
+  UY: "858",
+  UZ: "860",
+  VE: "862",
+  VN: "704",
+  ZA: "710",
+  ZW: "716",
+};
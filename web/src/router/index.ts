@@ -1,5 +1,8 @@
 import { createRouter, createWebHistory } from "vue-router";
 import Dashboard from "../components/Dashboard.vue";
+import Jobs from "../components/Jobs.vue";
+import RepoDetail from "../components/RepoDetail.vue";
+import UserDetail from "../components/UserDetail.vue";
 
 const router = createRouter({
   history: createWebHistory(import.meta.env.BASE_URL),
@@ -14,6 +17,21 @@ const router = createRouter({
       name: "about",
       component: Dashboard,
     },
+    {
+      path: "/jobs",
+      name: "jobs",
+      component: Jobs,
+    },
+    {
+      path: "/repos/:owner/:name",
+      name: "repo",
+      component: RepoDetail,
+    },
+    {
+      path: "/users/:login",
+      name: "user",
+      component: UserDetail,
+    },
   ],
 });
 